@@ -13,9 +13,21 @@
 
 class BackupController extends Controller
 {
-    public function index(): JsonResponse
+    public function index(Request $request): JsonResponse
     {
-        return response()->json(Backup::all());
+        // ?max_database_version=957 lists only backups a server with that
+        // internal database version can restore; ?server_token=... only the
+        // backups of that server.
+        $backups = Backup::query()
+            ->when($request->filled('max_database_version'), function ($query) use ($request) {
+                $query->where('database_version', '<=', $request->integer('max_database_version'));
+            })
+            ->when($request->filled('server_token'), function ($query) use ($request) {
+                $query->whereHas('server', fn ($server) => $server->where('token', $request->input('server_token')));
+            })
+            ->get();
+
+        return response()->json($backups);
     }
 
     public function download(Request $request, Backup $backup): JsonResponse
@@ -33,15 +33,12 @@ public function upload(BackupUploadRequest $request): JsonResponse
         $filePath = $file->storeAs("backups/{$serverName}/{$dbName}", $fileName, 'local');
 
         $backup = Backup::create([
+            ...$request->backupAttributes(),
             'user_id' => auth()->id(),
             'server_id' => $server->id,
             'db_name' => $validated['database_name'],
             'file_path' => $filePath,
             'file_size_bytes' => $file->getSize(),
-            'checksum_sha256' => $validated['checksum_sha256'],
-            'backup_started_at' => $validated['backup_started_at'],
-            'backup_completed_at' => $validated['backup_completed_at'],
-            'duration_seconds' => $validated['duration_seconds'],
             'status' => 'success',
         ]);
 
@@ -0,0 +1,54 @@
+<?php
+
+namespace App\Http\Controllers;
+
+use App\Models\ForeignBackup;
+use App\Models\Server;
+use Illuminate\Http\JsonResponse;
+use Illuminate\Http\Request;
+
+class ForeignBackupController extends Controller
+{
+    /**
+     * Store backups an agent found in its server's msdb history.
+     *
+     * @param Request $request
+     * @return JsonResponse
+     */
+    public function store(Request $request): JsonResponse
+    {
+        $validated = $request->validate([
+            'token' => 'required|string|exists:servers,token',
+            'backups' => 'required|array',
+            'backups.*.backup_set_id' => 'required|integer',
+            'backups.*.database' => 'required|string|max:255',
+            'backups.*.backup_type' => 'required|string|in:full,differential,log',
+            'backups.*.copy_only' => 'required|boolean',
+            'backups.*.started_at' => 'nullable|date',
+            'backups.*.finished_at' => 'nullable|date',
+            'backups.*.user_name' => 'nullable|string|max:255',
+            'backups.*.device' => 'nullable|string|max:1024',
+            'backups.*.impact' => 'required|string|in:none,breaks_differential_base,breaks_log_chain',
+        ]);
+
+        $server = Server::where('token', $validated['token'])->firstOrFail();
+
+        foreach ($validated['backups'] as $backup) {
+            ForeignBackup::updateOrCreate(
+                ['server_id' => $server->id, 'backup_set_id' => $backup['backup_set_id']],
+                [
+                    'db_name' => $backup['database'],
+                    'backup_type' => $backup['backup_type'],
+                    'is_copy_only' => $backup['copy_only'],
+                    'backup_started_at' => $backup['started_at'] ?? null,
+                    'backup_completed_at' => $backup['finished_at'] ?? null,
+                    'user_name' => $backup['user_name'] ?? null,
+                    'device' => $backup['device'] ?? null,
+                    'impact' => $backup['impact'],
+                ]
+            );
+        }
+
+        return response()->json(['status' => 'ok']);
+    }
+}
@@ -0,0 +1,217 @@
+<?php
+
+namespace App\Http\Controllers;
+
+use App\Http\Requests\UploadSessionCompleteRequest;
+use App\Models\Backup;
+use App\Models\Server;
+use App\Models\UploadSession;
+use Illuminate\Http\JsonResponse;
+use Illuminate\Http\Request;
+use Illuminate\Support\Facades\DB;
+use Illuminate\Support\Facades\Storage;
+use Illuminate\Support\Str;
+
+/**
+ * Chunked, resumable backup uploads: start a session, PUT the chunks in
+ * order, ask for the received offset after an interruption, then complete
+ * it with the backup metadata.
+ */
+class UploadSessionController extends Controller
+{
+    /**
+     * Start an upload session.
+     *
+     * @param Request $request
+     * @return JsonResponse
+     */
+    public function store(Request $request): JsonResponse
+    {
+        $validated = $request->validate([
+            'token' => 'required|string|exists:servers,token',
+            'database_name' => 'required|string|max:255',
+            'file_name' => 'required|string|max:255',
+            'file_size_bytes' => 'required|integer|min:0',
+            'chunk_size_bytes' => 'required|integer|min:1048576|max:67108864',
+        ]);
+
+        $server = Server::where('token', $validated['token'])->firstOrFail();
+
+        $session = new UploadSession([
+            'server_id' => $server->id,
+            'user_id' => auth()->id(),
+            'db_name' => $validated['database_name'],
+            'file_name' => basename($validated['file_name']),
+            'file_size_bytes' => $validated['file_size_bytes'],
+            'chunk_size_bytes' => $validated['chunk_size_bytes'],
+            'received_bytes' => 0,
+        ]);
+        $session->id = (string) Str::uuid();
+        $session->temp_path = "uploads/{$session->id}.part";
+        Storage::disk('local')->put($session->temp_path, '');
+        $session->save();
+
+        return $this->state($session);
+    }
+
+    /**
+     * Report how much of the file has been received.
+     *
+     * @param UploadSession $uploadSession
+     * @return JsonResponse
+     */
+    public function show(UploadSession $uploadSession): JsonResponse
+    {
+        $this->authorizeSession($uploadSession);
+
+        return $this->state($uploadSession);
+    }
+
+    /**
+     * Append one chunk. The body is the raw chunk; `Upload-Offset` is its
+     * position in the file and `Upload-Checksum-Sha256` its hex digest.
+     *
+     * @param Request $request
+     * @param UploadSession $uploadSession
+     * @param int $index
+     * @return JsonResponse
+     */
+    public function chunk(Request $request, UploadSession $uploadSession, int $index): JsonResponse
+    {
+        $this->authorizeSession($uploadSession);
+
+        $data = $request->getContent();
+        $length = strlen($data);
+        $offset = (int) $request->header('Upload-Offset', '-1');
+        $chunkSize = $uploadSession->chunk_size_bytes;
+
+        if ($offset !== $index * $chunkSize) {
+            return $this->error('Upload-Offset does not match the chunk index.', 422);
+        }
+        if ($length === 0 || $offset + $length > $uploadSession->file_size_bytes
+            || ($length !== $chunkSize && $offset + $length !== $uploadSession->file_size_bytes)) {
+            return $this->error('Only the last chunk may be shorter than the chunk size.', 422);
+        }
+        if (! hash_equals(strtolower((string) $request->header('Upload-Checksum-Sha256')), hash('sha256', $data))) {
+            return $this->error('Chunk checksum mismatch.', 422);
+        }
+
+        return DB::transaction(function () use ($uploadSession, $data, $offset, $length) {
+            $session = UploadSession::whereKey($uploadSession->getKey())->lockForUpdate()->firstOrFail();
+
+            if ($session->status !== 'uploading') {
+                return $this->error('The upload is already complete.', 409);
+            }
+            // A chunk sent again because its response was lost.
+            if ($offset + $length <= $session->received_bytes) {
+                return $this->state($session);
+            }
+            if ($offset !== $session->received_bytes) {
+                return $this->error("Expected the chunk at offset {$session->received_bytes}.", 409, $session);
+            }
+
+            // Drop anything a failed request wrote past the acknowledged offset.
+            $handle = fopen(Storage::disk('local')->path($session->temp_path), 'c');
+            ftruncate($handle, $session->received_bytes);
+            fseek($handle, $session->received_bytes);
+            $written = fwrite($handle, $data);
+            fclose($handle);
+            if ($written !== $length) {
+                return $this->error('Failed to store the chunk.', 500, $session);
+            }
+
+            $session->received_bytes += $length;
+            $session->save();
+
+            return $this->state($session);
+        });
+    }
+
+    /**
+     * Check the whole file against its checksum and create the backup.
+     *
+     * @param UploadSessionCompleteRequest $request
+     * @param UploadSession $uploadSession
+     * @return JsonResponse
+     */
+    public function complete(UploadSessionCompleteRequest $request, UploadSession $uploadSession): JsonResponse
+    {
+        $this->authorizeSession($uploadSession);
+
+        // The agent retries when the response is lost.
+        if ($uploadSession->status === 'completed') {
+            return response()->json([
+                'status' => 'ok',
+                'backup_id' => $uploadSession->backup_id,
+            ]);
+        }
+        if ($uploadSession->received_bytes !== $uploadSession->file_size_bytes) {
+            return $this->error('The upload is incomplete.', 409, $uploadSession);
+        }
+
+        $validated = $request->validated();
+        $disk = Storage::disk('local');
+        if (! hash_equals($validated['checksum_sha256'], hash_file('sha256', $disk->path($uploadSession->temp_path)))) {
+            return $this->error('Checksum mismatch.', 422, $uploadSession);
+        }
+
+        $server = $uploadSession->server;
+        $serverName = Str::slug($server->name);
+        $dbName = Str::slug($uploadSession->db_name);
+        $timestamp = now()->format('Ymd_His');
+        $attributes = $request->backupAttributes();
+        $originalName = pathinfo(preg_replace('/(\.zst)?(\.enc)?$/', '', $uploadSession->file_name), PATHINFO_FILENAME);
+        $extension = '.bak'
+            .($attributes['compression_codec'] === 'zstd' ? '.zst' : '')
+            .($attributes['encryption_algorithm'] !== null ? '.enc' : '');
+        $filePath = "backups/{$serverName}/{$dbName}/{$timestamp}_{$originalName}{$extension}";
+        $disk->move($uploadSession->temp_path, $filePath);
+
+        $backup = Backup::create([
+            ...$attributes,
+            'user_id' => $uploadSession->user_id,
+            'server_id' => $server->id,
+            'db_name' => $uploadSession->db_name,
+            'file_path' => $filePath,
+            'file_size_bytes' => $uploadSession->file_size_bytes,
+            'status' => 'success',
+        ]);
+
+        $uploadSession->update([
+            'status' => 'completed',
+            'backup_id' => $backup->id,
+        ]);
+
+        return response()->json([
+            'status' => 'ok',
+            'backup_id' => $backup->id,
+        ]);
+    }
+
+    private function authorizeSession(UploadSession $uploadSession): void
+    {
+        if ($uploadSession->user_id !== auth()->id()) {
+            abort(403);
+        }
+    }
+
+    private function state(UploadSession $session): JsonResponse
+    {
+        return response()->json([
+            'status' => 'ok',
+            'upload_id' => $session->id,
+            'file_size_bytes' => $session->file_size_bytes,
+            'chunk_size_bytes' => $session->chunk_size_bytes,
+            'received_bytes' => $session->received_bytes,
+        ]);
+    }
+
+    private function error(string $message, int $status, ?UploadSession $session = null): JsonResponse
+    {
+        return response()->json(array_filter([
+            'status' => 'error',
+            'message' => $message,
+            'received_bytes' => $session?->received_bytes,
+        ], fn ($value) => $value !== null), $status);
+    }
+}
@@ -24,11 +24,75 @@ public function rules(): array
         return [
             'token' => 'required|string|exists:servers,token',
             'database_name' => 'required|string|max:255',
+            'backup_type' => 'sometimes|string|in:full,differential,log',
+            'is_copy_only' => 'sometimes|boolean',
+            'base_backup_id' => 'nullable|integer|exists:backups,id',
+            'first_lsn' => 'nullable|numeric',
+            'last_lsn' => 'nullable|numeric',
+            'checkpoint_lsn' => 'nullable|numeric',
+            'database_backup_lsn' => 'nullable|numeric',
+            'sql_server_version' => 'nullable|string|max:32',
+            'database_version' => 'nullable|integer',
+            'compatibility_level' => 'nullable|integer',
+            'collation' => 'nullable|string|max:128',
+            'backup_size_bytes' => 'nullable|integer|min:0',
+            'compressed_size_bytes' => 'nullable|integer|min:0',
+            'file_list' => 'nullable|json',
+            'restore_test_status' => 'nullable|string|in:passed,failed',
+            'restore_test_message' => 'nullable|string',
+            'restore_tested_at' => 'nullable|date',
             'backup_started_at' => 'required|date',
             'backup_completed_at' => 'required|date|after_or_equal:backup_started_at',
             'duration_seconds' => 'required|numeric|min:0',
             'checksum_sha256' => 'required|string|size:64',
+            'compression_codec' => 'nullable|string|in:zstd',
+            'encryption_algorithm' => 'nullable|string|in:aes-256-gcm',
+            'encryption_key_id' => 'required_with:encryption_algorithm|nullable|string|max:255',
+            'raw_size_bytes' => 'required_with:compression_codec,encryption_algorithm|nullable|integer|min:0',
+            // Encrypted backups carry a keyed checksum instead, so the plaintext hash is never stored.
+            'raw_checksum_sha256' => 'exclude_with:encryption_algorithm|required_with:compression_codec|nullable|string|size:64',
+            'raw_checksum_hmac_sha256' => 'required_with:encryption_algorithm|nullable|string|size:64',
             'backup_file' => 'required|file',
         ];
     }
+
+    /**
+     * The validated backup metadata, as attributes of a new Backup.
+     *
+     * @return array<string, mixed>
+     */
+    public function backupAttributes(): array
+    {
+        $validated = $this->validated();
+
+        return [
+            'backup_type' => $validated['backup_type'] ?? 'full',
+            'is_copy_only' => $validated['is_copy_only'] ?? false,
+            'base_backup_id' => $validated['base_backup_id'] ?? null,
+            'first_lsn' => $validated['first_lsn'] ?? null,
+            'last_lsn' => $validated['last_lsn'] ?? null,
+            'checkpoint_lsn' => $validated['checkpoint_lsn'] ?? null,
+            'database_backup_lsn' => $validated['database_backup_lsn'] ?? null,
+            'sql_server_version' => $validated['sql_server_version'] ?? null,
+            'database_version' => $validated['database_version'] ?? null,
+            'compatibility_level' => $validated['compatibility_level'] ?? null,
+            'collation' => $validated['collation'] ?? null,
+            'backup_size_bytes' => $validated['backup_size_bytes'] ?? null,
+            'compressed_size_bytes' => $validated['compressed_size_bytes'] ?? null,
+            'file_list' => isset($validated['file_list']) ? json_decode($validated['file_list'], true) : null,
+            'checksum_sha256' => $validated['checksum_sha256'],
+            'compression_codec' => $validated['compression_codec'] ?? null,
+            'encryption_algorithm' => $validated['encryption_algorithm'] ?? null,
+            'encryption_key_id' => $validated['encryption_key_id'] ?? null,
+            'raw_size_bytes' => $validated['raw_size_bytes'] ?? null,
+            'raw_checksum_sha256' => $validated['raw_checksum_sha256'] ?? null,
+            'raw_checksum_hmac_sha256' => $validated['raw_checksum_hmac_sha256'] ?? null,
+            'backup_started_at' => $validated['backup_started_at'],
+            'backup_completed_at' => $validated['backup_completed_at'],
+            'duration_seconds' => $validated['duration_seconds'],
+            'restore_test_status' => $validated['restore_test_status'] ?? null,
+            'restore_test_message' => $validated['restore_test_message'] ?? null,
+            'restore_tested_at' => $validated['restore_tested_at'] ?? null,
+        ];
+    }
 }
@@ -0,0 +1,23 @@
+<?php
+
+namespace App\Http\Requests;
+
+use Illuminate\Support\Arr;
+
+/**
+ * The backup metadata sent to complete a chunked upload. The server and
+ * database were given when the upload started, and the file is already
+ * stored.
+ */
+class UploadSessionCompleteRequest extends BackupUploadRequest
+{
+    /**
+     * Get the validation rules that apply to the request.
+     *
+     * @return array<string, \Illuminate\Contracts\Validation\ValidationRule|array<mixed>|string>
+     */
+    public function rules(): array
+    {
+        return Arr::except(parent::rules(), ['token', 'database_name', 'backup_file']);
+    }
+}
@@ -19,6 +19,11 @@ public function server()
         return $this->belongsTo(Server::class);
     }
 
+    public function baseBackup()
+    {
+        return $this->belongsTo(Backup::class, 'base_backup_id');
+    }
+
     /**
      * The attributes that are mass assignable.
      *
@@ -28,13 +33,36 @@ public function server()
         'user_id',
         'server_id',
         'db_name',
+        'backup_type',
+        'is_copy_only',
+        'base_backup_id',
+        'first_lsn',
+        'last_lsn',
+        'checkpoint_lsn',
+        'database_backup_lsn',
+        'sql_server_version',
+        'database_version',
+        'compatibility_level',
+        'collation',
         'file_path',
         'file_size_bytes',
+        'backup_size_bytes',
+        'compressed_size_bytes',
+        'file_list',
         'checksum_sha256',
+        'compression_codec',
+        'raw_size_bytes',
+        'raw_checksum_sha256',
+        'raw_checksum_hmac_sha256',
+        'encryption_algorithm',
+        'encryption_key_id',
         'backup_started_at',
         'backup_completed_at',
         'duration_seconds',
         'status',
+        'restore_test_status',
+        'restore_test_message',
+        'restore_tested_at',
     ];
 
     /**
@@ -45,7 +73,15 @@ public function server()
     protected $casts = [
         'backup_started_at' => 'datetime',
         'backup_completed_at' => 'datetime',
+        'restore_tested_at' => 'datetime',
         'file_size_bytes' => 'integer',
+        'backup_size_bytes' => 'integer',
+        'compressed_size_bytes' => 'integer',
+        'raw_size_bytes' => 'integer',
+        'database_version' => 'integer',
+        'compatibility_level' => 'integer',
+        'file_list' => 'array',
         'duration_seconds' => 'integer',
+        'is_copy_only' => 'boolean',
     ];
 }
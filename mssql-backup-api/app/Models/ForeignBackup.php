@@ -0,0 +1,48 @@
+<?php
+
+namespace App\Models;
+
+use Illuminate\Database\Eloquent\Factories\HasFactory;
+use Illuminate\Database\Eloquent\Model;
+
+/**
+ * A backup found in a server's msdb history that the agent did not take.
+ */
+class ForeignBackup extends Model
+{
+    use HasFactory;
+
+    public function server()
+    {
+        return $this->belongsTo(Server::class);
+    }
+
+    /**
+     * The attributes that are mass assignable.
+     *
+     * @var array<int, string>
+     */
+    protected $fillable = [
+        'server_id',
+        'backup_set_id',
+        'db_name',
+        'backup_type',
+        'is_copy_only',
+        'backup_started_at',
+        'backup_completed_at',
+        'user_name',
+        'device',
+        'impact',
+    ];
+
+    /**
+     * The attributes that should be cast.
+     *
+     * @var array<string, string>
+     */
+    protected $casts = [
+        'backup_started_at' => 'datetime',
+        'backup_completed_at' => 'datetime',
+        'is_copy_only' => 'boolean',
+    ];
+}
@@ -0,0 +1,55 @@
+<?php
+
+namespace App\Models;
+
+use Illuminate\Database\Eloquent\Concerns\HasUuids;
+use Illuminate\Database\Eloquent\Model;
+
+/**
+ * A chunked backup upload in progress. Chunks are appended to `temp_path`
+ * until `received_bytes` reaches `file_size_bytes`.
+ */
+class UploadSession extends Model
+{
+    use HasUuids;
+
+    public function server()
+    {
+        return $this->belongsTo(Server::class);
+    }
+
+    public function backup()
+    {
+        return $this->belongsTo(Backup::class);
+    }
+
+    /**
+     * The attributes that are mass assignable.
+     *
+     * @var array<int, string>
+     */
+    protected $fillable = [
+        'server_id',
+        'user_id',
+        'db_name',
+        'file_name',
+        'file_size_bytes',
+        'chunk_size_bytes',
+        'received_bytes',
+        'temp_path',
+        'status',
+        'backup_id',
+    ];
+
+    /**
+     * The attributes that should be cast.
+     *
+     * @var array<string, string>
+     */
+    protected $casts = [
+        'user_id' => 'integer',
+        'file_size_bytes' => 'integer',
+        'chunk_size_bytes' => 'integer',
+        'received_bytes' => 'integer',
+    ];
+}
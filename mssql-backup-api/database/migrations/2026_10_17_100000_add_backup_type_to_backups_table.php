@@ -0,0 +1,30 @@
+<?php
+
+use Illuminate\Database\Migrations\Migration;
+use Illuminate\Database\Schema\Blueprint;
+use Illuminate\Support\Facades\Schema;
+
+return new class extends Migration
+{
+    /**
+     * Run the migrations.
+     */
+    public function up(): void
+    {
+        Schema::table('backups', function (Blueprint $table) {
+            $table->string('backup_type')->default('full')->after('db_name'); // full | differential
+            $table->foreignId('base_backup_id')->nullable()->after('backup_type')->constrained('backups')->nullOnDelete();
+        });
+    }
+
+    /**
+     * Reverse the migrations.
+     */
+    public function down(): void
+    {
+        Schema::table('backups', function (Blueprint $table) {
+            $table->dropConstrainedForeignId('base_backup_id');
+            $table->dropColumn('backup_type');
+        });
+    }
+};
@@ -0,0 +1,31 @@
+<?php
+
+use Illuminate\Database\Migrations\Migration;
+use Illuminate\Database\Schema\Blueprint;
+use Illuminate\Support\Facades\Schema;
+
+return new class extends Migration
+{
+    /**
+     * Run the migrations.
+     */
+    public function up(): void
+    {
+        Schema::table('backups', function (Blueprint $table) {
+            $table->decimal('first_lsn', 25, 0)->nullable()->after('base_backup_id');
+            $table->decimal('last_lsn', 25, 0)->nullable()->after('first_lsn');
+            $table->decimal('checkpoint_lsn', 25, 0)->nullable()->after('last_lsn');
+            $table->decimal('database_backup_lsn', 25, 0)->nullable()->after('checkpoint_lsn');
+        });
+    }
+
+    /**
+     * Reverse the migrations.
+     */
+    public function down(): void
+    {
+        Schema::table('backups', function (Blueprint $table) {
+            $table->dropColumn(['first_lsn', 'last_lsn', 'checkpoint_lsn', 'database_backup_lsn']);
+        });
+    }
+};
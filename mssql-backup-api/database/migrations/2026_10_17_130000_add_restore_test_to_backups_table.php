@@ -0,0 +1,30 @@
+<?php
+
+use Illuminate\Database\Migrations\Migration;
+use Illuminate\Database\Schema\Blueprint;
+use Illuminate\Support\Facades\Schema;
+
+return new class extends Migration
+{
+    /**
+     * Run the migrations.
+     */
+    public function up(): void
+    {
+        Schema::table('backups', function (Blueprint $table) {
+            $table->string('restore_test_status')->nullable()->after('status'); // passed | failed
+            $table->text('restore_test_message')->nullable()->after('restore_test_status');
+            $table->timestamp('restore_tested_at')->nullable()->after('restore_test_message');
+        });
+    }
+
+    /**
+     * Reverse the migrations.
+     */
+    public function down(): void
+    {
+        Schema::table('backups', function (Blueprint $table) {
+            $table->dropColumn(['restore_test_status', 'restore_test_message', 'restore_tested_at']);
+        });
+    }
+};
@@ -0,0 +1,42 @@
+<?php
+
+use Illuminate\Database\Migrations\Migration;
+use Illuminate\Database\Schema\Blueprint;
+use Illuminate\Support\Facades\Schema;
+
+return new class extends Migration
+{
+    /**
+     * Run the migrations.
+     */
+    public function up(): void
+    {
+        Schema::table('backups', function (Blueprint $table) {
+            $table->string('sql_server_version')->nullable()->after('database_backup_lsn');
+            $table->integer('database_version')->nullable()->after('sql_server_version');
+            $table->unsignedSmallInteger('compatibility_level')->nullable()->after('database_version');
+            $table->string('collation')->nullable()->after('compatibility_level');
+            $table->unsignedBigInteger('backup_size_bytes')->nullable()->after('file_size_bytes');
+            $table->unsignedBigInteger('compressed_size_bytes')->nullable()->after('backup_size_bytes');
+            $table->json('file_list')->nullable()->after('compressed_size_bytes');
+        });
+    }
+
+    /**
+     * Reverse the migrations.
+     */
+    public function down(): void
+    {
+        Schema::table('backups', function (Blueprint $table) {
+            $table->dropColumn([
+                'sql_server_version',
+                'database_version',
+                'compatibility_level',
+                'collation',
+                'backup_size_bytes',
+                'compressed_size_bytes',
+                'file_list',
+            ]);
+        });
+    }
+};
@@ -0,0 +1,39 @@
+<?php
+
+use Illuminate\Database\Migrations\Migration;
+use Illuminate\Database\Schema\Blueprint;
+use Illuminate\Support\Facades\Schema;
+
+return new class extends Migration
+{
+    /**
+     * Run the migrations.
+     */
+    public function up(): void
+    {
+        Schema::create('foreign_backups', function (Blueprint $table) {
+            $table->id();
+            $table->foreignId('server_id')->constrained()->onDelete('cascade');
+            $table->integer('backup_set_id'); // msdb.dbo.backupset.backup_set_id
+            $table->string('db_name');
+            $table->string('backup_type'); // full | differential | log
+            $table->boolean('is_copy_only')->default(false);
+            $table->dateTime('backup_started_at')->nullable();
+            $table->dateTime('backup_completed_at')->nullable();
+            $table->string('user_name')->nullable();
+            $table->string('device', 1024)->nullable();
+            $table->string('impact'); // none | breaks_differential_base | breaks_log_chain
+            $table->timestamps();
+
+            $table->unique(['server_id', 'backup_set_id']);
+        });
+    }
+
+    /**
+     * Reverse the migrations.
+     */
+    public function down(): void
+    {
+        Schema::dropIfExists('foreign_backups');
+    }
+};
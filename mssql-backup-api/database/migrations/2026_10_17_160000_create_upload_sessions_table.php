@@ -0,0 +1,37 @@
+<?php
+
+use Illuminate\Database\Migrations\Migration;
+use Illuminate\Database\Schema\Blueprint;
+use Illuminate\Support\Facades\Schema;
+
+return new class extends Migration
+{
+    /**
+     * Run the migrations.
+     */
+    public function up(): void
+    {
+        Schema::create('upload_sessions', function (Blueprint $table) {
+            $table->uuid('id')->primary();
+            $table->foreignId('server_id')->constrained()->onDelete('cascade');
+            $table->foreignId('user_id')->nullable()->constrained()->onDelete('set null');
+            $table->string('db_name');
+            $table->string('file_name');
+            $table->unsignedBigInteger('file_size_bytes');
+            $table->unsignedInteger('chunk_size_bytes');
+            $table->unsignedBigInteger('received_bytes')->default(0);
+            $table->string('temp_path');
+            $table->string('status')->default('uploading'); // uploading | completed
+            $table->foreignId('backup_id')->nullable()->constrained()->onDelete('set null');
+            $table->timestamps();
+        });
+    }
+
+    /**
+     * Reverse the migrations.
+     */
+    public function down(): void
+    {
+        Schema::dropIfExists('upload_sessions');
+    }
+};
@@ -0,0 +1,34 @@
+<?php
+
+use Illuminate\Database\Migrations\Migration;
+use Illuminate\Database\Schema\Blueprint;
+use Illuminate\Support\Facades\Schema;
+
+return new class extends Migration
+{
+    /**
+     * Run the migrations.
+     */
+    public function up(): void
+    {
+        Schema::table('backups', function (Blueprint $table) {
+            $table->string('compression_codec', 16)->nullable()->after('file_size_bytes');
+            $table->unsignedBigInteger('raw_size_bytes')->nullable()->after('compression_codec');
+            $table->string('raw_checksum_sha256', 64)->nullable()->after('checksum_sha256');
+        });
+    }
+
+    /**
+     * Reverse the migrations.
+     */
+    public function down(): void
+    {
+        Schema::table('backups', function (Blueprint $table) {
+            $table->dropColumn([
+                'compression_codec',
+                'raw_size_bytes',
+                'raw_checksum_sha256',
+            ]);
+        });
+    }
+};
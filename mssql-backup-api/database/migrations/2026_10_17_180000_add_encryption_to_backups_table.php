@@ -0,0 +1,30 @@
+<?php
+
+use Illuminate\Database\Migrations\Migration;
+use Illuminate\Database\Schema\Blueprint;
+use Illuminate\Support\Facades\Schema;
+
+return new class extends Migration
+{
+    /**
+     * Run the migrations.
+     */
+    public function up(): void
+    {
+        Schema::table('backups', function (Blueprint $table) {
+            $table->string('encryption_algorithm', 32)->nullable()->after('raw_size_bytes');
+            $table->string('encryption_key_id')->nullable()->index()->after('encryption_algorithm');
+        });
+    }
+
+    /**
+     * Reverse the migrations.
+     */
+    public function down(): void
+    {
+        Schema::table('backups', function (Blueprint $table) {
+            $table->dropIndex(['encryption_key_id']);
+            $table->dropColumn(['encryption_algorithm', 'encryption_key_id']);
+        });
+    }
+};
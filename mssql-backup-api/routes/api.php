@@ -2,6 +2,8 @@
 
 use App\Http\Controllers\BackupController;
 use App\Http\Controllers\BackupUploadController;
+use App\Http\Controllers\ForeignBackupController;
+use App\Http\Controllers\UploadSessionController;
 use Illuminate\Http\Request;
 use Illuminate\Support\Facades\Route;
 
@@ -11,6 +13,12 @@
     });
 
     Route::post('/backups/upload', [BackupUploadController::class, 'upload']);
+    Route::post('/backups/uploads', [UploadSessionController::class, 'store']);
+    Route::get('/backups/uploads/{uploadSession}', [UploadSessionController::class, 'show']);
+    Route::put('/backups/uploads/{uploadSession}/chunks/{index}', [UploadSessionController::class, 'chunk'])
+        ->whereNumber('index');
+    Route::post('/backups/uploads/{uploadSession}/complete', [UploadSessionController::class, 'complete']);
+    Route::post('/backups/foreign', [ForeignBackupController::class, 'store']);
     Route::get('/backups', [BackupController::class, 'index']);
     Route::get('/backups/{backup}/download', [BackupController::class, 'download']);
 });
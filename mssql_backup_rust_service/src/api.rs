@@ -0,0 +1,174 @@
+use crate::config::Config;
+use crate::history::ForeignBackup;
+use anyhow::{bail, Result};
+use futures::StreamExt;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use tokio::io::AsyncWriteExt;
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct BackupEntry {
+    pub id: u64,
+    #[serde(default)]
+    pub server_id: Option<u64>,
+    pub db_name: String,
+    #[serde(default = "default_backup_type")]
+    pub backup_type: String,
+    #[serde(default)]
+    pub base_backup_id: Option<u64>,
+    #[serde(default, deserialize_with = "lsn")]
+    pub first_lsn: Option<u128>,
+    #[serde(default, deserialize_with = "lsn")]
+    pub last_lsn: Option<u128>,
+    #[serde(default, deserialize_with = "lsn")]
+    pub checkpoint_lsn: Option<u128>,
+    #[serde(default, deserialize_with = "lsn")]
+    pub database_backup_lsn: Option<u128>,
+    pub backup_started_at: String,
+    pub backup_completed_at: String,
+    pub status: String,
+    #[serde(default)]
+    pub checksum_sha256: String,
+    /// Set when the stored file is compressed; `checksum_sha256` is then
+    /// that of the compressed file.
+    #[serde(default)]
+    pub compression_codec: Option<String>,
+    #[serde(default)]
+    pub encryption_algorithm: Option<String>,
+    #[serde(default)]
+    pub raw_checksum_sha256: Option<String>,
+    /// Replaces `raw_checksum_sha256` for encrypted backups.
+    #[serde(default)]
+    pub raw_checksum_hmac_sha256: Option<String>,
+}
+
+impl BackupEntry {
+    /// The stored file is not the backup as SQL Server wrote it.
+    pub fn is_encoded(&self) -> bool {
+        self.compression_codec.is_some() || self.encryption_algorithm.is_some()
+    }
+}
+
+fn default_backup_type() -> String {
+    "full".to_string()
+}
+
+/// LSNs are stored as decimal(25,0), which the API returns as a string (or a
+/// number, depending on the database driver).
+fn lsn<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<u128>, D::Error> {
+    let value = <Option<serde_json::Value> as serde::Deserialize>::deserialize(deserializer)?;
+    Ok(match value {
+        Some(serde_json::Value::String(s)) => s.split('.').next().and_then(|s| s.parse().ok()),
+        Some(serde_json::Value::Number(n)) => n.as_u64().map(u128::from),
+        _ => None,
+    })
+}
+
+pub async fn fetch_backups(config: Config) -> Result<Vec<BackupEntry>, String> {
+    fetch_catalog(&config, &[]).await
+}
+
+/// Backups of the server this agent uploads as.
+pub async fn fetch_server_backups(config: Config) -> Result<Vec<BackupEntry>, String> {
+    fetch_catalog(&config, &[("server_token", config.api.server_token.as_str())]).await
+}
+
+async fn fetch_catalog(config: &Config, query: &[(&str, &str)]) -> Result<Vec<BackupEntry>, String> {
+    let client = reqwest::Client::new();
+    let url = format!("{}/api/backups", config.api.url.trim_end_matches('/'));
+    let response = client
+        .get(&url)
+        .query(query)
+        .bearer_auth(&config.api.auth_token)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if response.status().is_success() {
+        let backups = response
+            .json::<Vec<BackupEntry>>()
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(backups)
+    } else {
+        Err(format!("Failed to fetch backups: {}", response.status()))
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct DownloadUrl {
+    url: String,
+}
+
+pub async fn request_download_link(config: Config, backup_id: u64) -> Result<String, String> {
+    let client = reqwest::Client::new();
+    let url = format!(
+        "{}/api/backups/{}/download",
+        config.api.url.trim_end_matches('/'),
+        backup_id
+    );
+    let response = client
+        .get(&url)
+        .bearer_auth(&config.api.auth_token)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if response.status().is_success() {
+        let download_url = response
+            .json::<DownloadUrl>()
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(download_url.url)
+    } else {
+        Err(format!(
+            "Failed to request download link: {}",
+            response.status()
+        ))
+    }
+}
+
+/// Downloads `url` to `dest` and returns the SHA-256 of the downloaded bytes.
+pub async fn download_file(url: &str, dest: &Path) -> Result<String> {
+    let response = reqwest::get(url).await?;
+    if !response.status().is_success() {
+        bail!("Download failed with status: {}", response.status());
+    }
+
+    let mut file = tokio::fs::File::create(dest).await?;
+    let mut hasher = Sha256::new();
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        hasher.update(&chunk);
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[derive(serde::Serialize)]
+struct ForeignBackupReport<'a> {
+    token: &'a str,
+    backups: &'a [ForeignBackup],
+}
+
+/// Sends backups found in the `msdb` history that the agent did not take.
+pub async fn report_foreign_backups(config: &Config, backups: &[ForeignBackup]) -> Result<()> {
+    let url = format!("{}/api/backups/foreign", config.api.url.trim_end_matches('/'));
+    let response = reqwest::Client::new()
+        .post(&url)
+        .bearer_auth(&config.api.auth_token)
+        .header("Accept", "application/json")
+        .json(&ForeignBackupReport {
+            token: &config.api.server_token,
+            backups,
+        })
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        bail!("Failed to report foreign backups: {}", response.status());
+    }
+    Ok(())
+}
@@ -1,77 +1,579 @@
-use crate::config::Config;
-use anyhow::{bail, Result};
+use crate::config::{
+    BackupDirs, BackupOptions, Config, DatabaseConfig, DiscoveryConfig, Encryption, MssqlConfig,
+    TlsConfig,
+};
+use crate::connection::{self, Connection};
+#[cfg(unix)]
+use crate::kerberos;
+use crate::preflight;
+use crate::progress;
+use crate::restore;
+use crate::sql::{self, WithOptions};
+use anyhow::{anyhow, bail, Context, Result};
+use glob::{MatchOptions, Pattern};
+use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
-use tiberius::{AuthMethod, Client, Config as TiberiusConfig, SqlBrowser};
+use tiberius::numeric::Numeric;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tiberius::{AuthMethod, Client, Config as TiberiusConfig, EncryptionLevel, SqlBrowser};
 use tokio::net::TcpStream;
-use tokio_util::compat::TokioAsyncReadCompatExt;
+use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
 use time::OffsetDateTime;
 use time::macros::format_description;
 
-pub async fn perform_backup(config: &Config) -> Result<PathBuf> {
-    tracing::info!("Starting perform backup");
+pub(crate) type MssqlClient = Client<Compat<TcpStream>>;
+
+/// `DESCRIPTION` of every backup set the agent writes, so its own backups
+/// can be told apart in the `msdb` history.
+pub const BACKUP_DESCRIPTION: &str = "mssql_backup_rust_service";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BackupType {
+    Full,
+    Differential,
+    Log,
+}
+
+impl BackupType {
+    /// The value sent to the API as `backup_type`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BackupType::Full => "full",
+            BackupType::Differential => "differential",
+            BackupType::Log => "log",
+        }
+    }
+}
+
+/// A backup file by the path SQL Server uses and the path the agent reads.
+#[derive(Debug, Clone)]
+pub struct BackupLocation {
+    pub server: String,
+    pub agent: PathBuf,
+}
+
+impl BackupLocation {
+    pub fn new(dirs: &BackupDirs, file_name: &str) -> Self {
+        let agent = Path::new(&dirs.agent).join(file_name);
+        let server = if dirs.server == dirs.agent {
+            agent.to_string_lossy().into_owned()
+        } else {
+            server_path_join(&dirs.server, file_name)
+        };
+        Self { server, agent }
+    }
+}
+
+/// Joins a server-side directory and file name using the server's separator,
+/// which may differ from the agent's (SQL Server on Linux, remote instances).
+pub(crate) fn server_path_join(dir: &str, file_name: &str) -> String {
+    let separator = if dir.contains('\\') { '\\' } else { '/' };
+    format!("{}{}{}", dir.trim_end_matches(['\\', '/']), separator, file_name)
+}
+
+/// LSNs of a finished backup as recorded in `msdb.dbo.backupset`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BackupLsns {
+    pub first_lsn: String,
+    pub last_lsn: String,
+    pub checkpoint_lsn: String,
+    /// Checkpoint LSN of the full backup that is the current differential base.
+    pub database_backup_lsn: String,
+}
+
+/// SQL Server error raised when a log backup has no full backup to build on,
+/// e.g. after the recovery model was switched to SIMPLE and back.
+const NO_CURRENT_DATABASE_BACKUP: u32 = 4214;
+
+/// Whether `error` means the log chain is broken and a new full backup is required.
+pub fn is_log_chain_missing(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<tiberius::error::Error>()
+        .and_then(|e| e.code())
+        == Some(NO_CURRENT_DATABASE_BACKUP)
+}
+
+impl std::fmt::Display for BackupType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+pub async fn perform_backup(
+    conn: &mut Connection,
+    config: &Config,
+    database: &DatabaseConfig,
+    backup_type: BackupType,
+    copy_only: bool,
+    backup_options: &BackupOptions,
+) -> Result<BackupLocation> {
+    tracing::info!(
+        "Starting perform {}{} backup of database '{}'",
+        backup_type,
+        if copy_only { " (copy-only)" } else { "" },
+        database.name
+    );
     let format = format_description!("[year][month][day]_[hour][minute][second]");
+    let (suffix, extension) = match backup_type {
+        BackupType::Full => ("", "bak"),
+        BackupType::Differential => ("_diff", "bak"),
+        BackupType::Log => ("_log", "trn"),
+    };
     let backup_filename = format!(
-        "{}_{}.bak",
-        config.mssql.database,
-        OffsetDateTime::now_utc().format(&format)?
+        "{}_{}{}.{}",
+        file_name_safe(&database.name),
+        OffsetDateTime::now_utc().format(&format)?,
+        suffix,
+        extension
     );
-    let backup_filepath = Path::new(&config.backup.temp_path).join(&backup_filename);
+    let dirs = database.backup_dirs(&config.backup);
+    let location = BackupLocation::new(&dirs, &backup_filename);
 
-    std::fs::create_dir_all(&config.backup.temp_path)?;
+    std::fs::create_dir_all(&dirs.agent)?;
 
-    let mut client = create_mssql_client(config).await.map_err(|e| {
+    let timeouts = conn.timeouts();
+    let client = conn.client().await.map_err(|e| {
         tracing::error!("Failed to create MSSQL client: {:?}", e);
         e
     })?;
 
-    let backup_path_str = match backup_filepath.to_str() {
-        Some(s) => s,
-        None => {
-            let err_msg = format!("Invalid backup path (contains non-UTF8 characters): {:?}", backup_filepath);
-            tracing::error!("{}", err_msg);
-            bail!(err_msg);
-        }
+    timeouts
+        .command(
+            "Pre-flight checks",
+            preflight::run_checks(
+                client,
+                &database.name,
+                backup_type,
+                Path::new(&dirs.agent),
+                config.upload.compression.enabled || config.upload.encryption.enabled,
+            ),
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!("Database '{}': {}", database.name, e);
+            e
+        })?;
+
+    let description = match backup_type {
+        BackupType::Full => "Full Database Backup",
+        BackupType::Differential => "Differential Database Backup",
+        BackupType::Log => "Transaction Log Backup",
     };
+    let mut options = WithOptions::new();
+    if backup_type == BackupType::Differential {
+        options.flag("DIFFERENTIAL");
+    }
+    if copy_only {
+        options.flag("COPY_ONLY");
+    }
+    let compression_supported = timeouts
+        .command("Edition query", supports_compression(client))
+        .await
+        .unwrap_or_else(|e| {
+        tracing::warn!("Could not determine the server edition, assuming no compression support: {:?}", e);
+        false
+    });
+    add_tuning_options(&mut options, backup_options, compression_supported);
+    options
+        .flag("NOFORMAT")
+        .flag("NOINIT")
+        .text("NAME", &format!("{}-{}", database.name, description))
+        .text("DESCRIPTION", BACKUP_DESCRIPTION)
+        .flag("SKIP")
+        .flag("NOREWIND")
+        .flag("NOUNLOAD")
+        .number("STATS", 10);
 
-    let backup_command = format!(
-        "BACKUP DATABASE [{}] TO DISK = N'{}' WITH NOFORMAT, NOINIT, NAME = N'{}-Full Database Backup', SKIP, NOREWIND, NOUNLOAD, STATS = 10",
-        config.mssql.database,
-        backup_path_str,
-        config.mssql.database
-    );
+    let backup_command = match backup_type {
+        BackupType::Full | BackupType::Differential => {
+            sql::backup_database(&database.name, &location.server, &options)
+        }
+        BackupType::Log => sql::backup_log(&database.name, &location.server, &options),
+    };
 
-    tracing::info!("Starting backup...");
-    client.execute(backup_command, &[]).await.map_err(|e| {
+    tracing::info!("Starting backup to {}...", location.server);
+    let name = format!("{} backup of '{}'", backup_type, database.name);
+    let backup = progress::track(name, &location.agent, None, client.execute(backup_command, &[]));
+    let result = timeouts.long_command("BACKUP", backup).await;
+    result.map_err(|e| {
         tracing::error!("Backup command failed: {:?}", e);
         e
     })?;
     tracing::info!("Backup command executed.");
 
-    Ok(backup_filepath)
+    Ok(location)
 }
 
-pub async fn verify_backup(config: &Config, backup_path: &Path) -> Result<()> {
-    let mut client = create_mssql_client(config).await.map_err(|e| {
-        tracing::error!("Failed to create MSSQL client for verification: {:?}", e);
+/// Replaces characters that are not safe in a file name (path separators,
+/// `..`, reserved Windows characters) so any database name maps to a file
+/// inside the temp directory.
+pub(crate) fn file_name_safe(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+/// Adds the `WITH` options for `backup_options`, leaving out the ones the
+/// server cannot use and values SQL Server would reject.
+fn add_tuning_options(options: &mut WithOptions, backup_options: &BackupOptions, compression_supported: bool) {
+    match backup_options.compression {
+        Some(true) if compression_supported => {
+            options.flag("COMPRESSION");
+        }
+        Some(false) if compression_supported => {
+            options.flag("NO_COMPRESSION");
+        }
+        Some(true) => tracing::warn!("Backup compression is not supported by this SQL Server edition; ignoring it."),
+        _ => {}
+    }
+    match backup_options.checksum {
+        Some(true) => {
+            options.flag("CHECKSUM");
+        }
+        Some(false) => {
+            options.flag("NO_CHECKSUM");
+        }
+        None => {}
+    }
+    match backup_options.continue_after_error {
+        Some(true) => {
+            options.flag("CONTINUE_AFTER_ERROR");
+        }
+        Some(false) => {
+            options.flag("STOP_ON_ERROR");
+        }
+        None => {}
+    }
+    if let Some(buffer_count) = backup_options.buffer_count {
+        if buffer_count > 0 {
+            options.number("BUFFERCOUNT", buffer_count.into());
+        } else {
+            tracing::warn!("Ignoring buffer_count of 0.");
+        }
+    }
+    if let Some(size) = backup_options.max_transfer_size {
+        if size > 0 && size % 65536 == 0 && size <= 4 * 1024 * 1024 {
+            options.number("MAXTRANSFERSIZE", size.into());
+        } else {
+            tracing::warn!("Ignoring max_transfer_size {}: must be a multiple of 64 KB up to 4 MB.", size);
+        }
+    }
+    if let Some(size) = backup_options.block_size {
+        if size.is_power_of_two() && (512..=65536).contains(&size) {
+            options.number("BLOCKSIZE", size.into());
+        } else {
+            tracing::warn!("Ignoring block_size {}: must be a power of two from 512 to 65536.", size);
+        }
+    }
+}
+
+/// Native backup compression is not available on Express and Web editions.
+async fn supports_compression(client: &mut MssqlClient) -> Result<bool> {
+    let row = client
+        .simple_query(
+            "SELECT CAST(SERVERPROPERTY('Edition') AS nvarchar(128)) AS edition, \
+                    CAST(SERVERPROPERTY('EngineEdition') AS int) AS engine_edition",
+        )
+        .await?
+        .into_row()
+        .await?;
+    let Some(row) = row else {
+        bail!("SERVERPROPERTY returned no rows");
+    };
+
+    let edition: &str = row.get("edition").unwrap_or_default();
+    let engine_edition: i32 = row.get("engine_edition").unwrap_or_default();
+    tracing::info!("Connected SQL Server edition: {}", edition);
+
+    // EngineEdition 4 is Express; Web reports as Standard (2) and is only identifiable by name.
+    Ok(engine_edition != 4 && !edition.contains("Express") && !edition.contains("Web"))
+}
+
+/// Reads the LSNs SQL Server recorded for the backup written to `location`.
+pub async fn read_backup_lsns(
+    conn: &mut Connection,
+    database: &DatabaseConfig,
+    location: &BackupLocation,
+) -> Result<BackupLsns> {
+    let timeouts = conn.timeouts();
+    let client = conn.client().await?;
+
+    let row = timeouts.command("Backup history query", async {
+        client
+            .query(
+            "SELECT TOP 1 \
+                CAST(b.first_lsn AS varchar(30)) AS first_lsn, \
+                CAST(b.last_lsn AS varchar(30)) AS last_lsn, \
+                CAST(b.checkpoint_lsn AS varchar(30)) AS checkpoint_lsn, \
+                CAST(b.database_backup_lsn AS varchar(30)) AS database_backup_lsn \
+             FROM msdb.dbo.backupset b \
+             JOIN msdb.dbo.backupmediafamily m ON m.media_set_id = b.media_set_id \
+             WHERE b.database_name = @P1 AND m.physical_device_name = @P2 \
+             ORDER BY b.backup_set_id DESC",
+                &[&database.name.as_str(), &location.server.as_str()],
+            )
+            .await?
+            .into_row()
+            .await
+    })
+    .await?;
+
+    let Some(row) = row else {
+        bail!("No msdb backup history found for {}", location.server);
+    };
+    let column = |name: &str| -> String { row.get::<&str, _>(name).unwrap_or_default().to_string() };
+
+    Ok(BackupLsns {
+        first_lsn: column("first_lsn"),
+        last_lsn: column("last_lsn"),
+        checkpoint_lsn: column("checkpoint_lsn"),
+        database_backup_lsn: column("database_backup_lsn"),
+    })
+}
+
+/// What `RESTORE HEADERONLY` and `RESTORE FILELISTONLY` report about a
+/// backup file, so the catalog can tell where it will restore.
+#[derive(Serialize, Debug, Clone)]
+pub struct BackupHeader {
+    /// Version of the instance that took the backup, e.g. "16.0.4135".
+    pub software_version: String,
+    /// Internal database version; a server can only restore versions up to its own.
+    pub database_version: i32,
+    pub compatibility_level: u8,
+    pub collation: String,
+    pub lsns: BackupLsns,
+    pub backup_size_bytes: u64,
+    /// Set for compressed backups.
+    pub compressed_size_bytes: Option<u64>,
+    pub files: Vec<restore::BackupFile>,
+}
+
+/// Reads the header and file list of the backup at `location`.
+pub async fn read_backup_header(conn: &mut Connection, location: &BackupLocation) -> Result<BackupHeader> {
+    let timeouts = conn.timeouts();
+    let client = conn.client().await?;
+
+    let row = timeouts
+        .command("RESTORE HEADERONLY", async {
+            client
+                .simple_query(sql::restore_headeronly(&location.server))
+                .await?
+                .into_row()
+                .await
+        })
+        .await?;
+    let Some(row) = row else {
+        bail!("RESTORE HEADERONLY returned no backup sets for {}", location.server);
+    };
+    let text = |name: &str| -> String { row.get::<&str, _>(name).unwrap_or_default().to_string() };
+    let numeric = |name: &str| -> Option<i128> { row.get::<Numeric, _>(name).map(|n| n.int_part()) };
+    let lsn = |name: &str| -> String { numeric(name).map(|n| n.to_string()).unwrap_or_default() };
+
+    let backup_size_bytes = numeric("BackupSize").unwrap_or(0).max(0) as u64;
+    let compressed_size_bytes = numeric("CompressedBackupSize")
+        .map(|n| n.max(0) as u64)
+        .filter(|&compressed| compressed < backup_size_bytes);
+    let header = BackupHeader {
+        software_version: format!(
+            "{}.{}.{}",
+            row.get::<i32, _>("SoftwareVersionMajor").unwrap_or(0),
+            row.get::<i32, _>("SoftwareVersionMinor").unwrap_or(0),
+            row.get::<i32, _>("SoftwareVersionBuild").unwrap_or(0)
+        ),
+        database_version: row.get::<i32, _>("DatabaseVersion").unwrap_or(0),
+        compatibility_level: row.get::<u8, _>("CompatibilityLevel").unwrap_or(0),
+        collation: text("Collation"),
+        lsns: BackupLsns {
+            first_lsn: lsn("FirstLSN"),
+            last_lsn: lsn("LastLSN"),
+            checkpoint_lsn: lsn("CheckpointLSN"),
+            database_backup_lsn: lsn("DatabaseBackupLSN"),
+        },
+        backup_size_bytes,
+        compressed_size_bytes,
+        files: Vec::new(),
+    };
+
+    Ok(BackupHeader {
+        files: timeouts
+            .command("RESTORE FILELISTONLY", restore::file_list(client, &location.server))
+            .await?,
+        ..header
+    })
+}
+
+/// Returns the recovery model of `database` (FULL, BULK_LOGGED or SIMPLE).
+pub async fn recovery_model(conn: &mut Connection, database: &DatabaseConfig) -> Result<String> {
+    let timeouts = conn.timeouts();
+    let client = conn.client().await?;
+    let row = timeouts
+        .command("Recovery model query", async {
+            client
+                .query(
+                    "SELECT recovery_model_desc FROM sys.databases WHERE name = @P1",
+                    &[&database.name.as_str()],
+                )
+                .await?
+                .into_row()
+                .await
+        })
+        .await?;
+
+    match row.and_then(|row| row.get::<&str, _>("recovery_model_desc").map(str::to_string)) {
+        Some(model) => Ok(model),
+        None => bail!("Database '{}' not found", database.name),
+    }
+}
+
+/// The local replica of a database in an Always On availability group.
+#[derive(Debug, Clone)]
+pub struct ReplicaRole {
+    pub group: String,
+    pub is_primary: bool,
+    /// `sys.fn_hadr_backup_is_preferred_replica`: whether backups should be
+    /// taken here under the group's backup preference.
+    pub is_preferred: bool,
+    /// The group's `automated_backup_preference_desc`, e.g. `secondary`.
+    pub preference: String,
+}
+
+/// Returns the availability group role of the local replica of `database`,
+/// or `None` if the database is not in an availability group.
+pub async fn replica_role(conn: &mut Connection, database: &DatabaseConfig) -> Result<Option<ReplicaRole>> {
+    let timeouts = conn.timeouts();
+    let client = conn.client().await?;
+    let row = timeouts
+        .command("Availability group query", async {
+            client
+                .query(
+                    "SELECT ag.name AS group_name, ag.automated_backup_preference_desc AS preference, \
+                        CAST(CASE WHEN rs.role_desc = 'PRIMARY' THEN 1 ELSE 0 END AS bit) AS is_primary, \
+                        CAST(sys.fn_hadr_backup_is_preferred_replica(d.name) AS bit) AS is_preferred \
+                     FROM sys.databases d \
+                     JOIN sys.availability_replicas r ON r.replica_id = d.replica_id \
+                     JOIN sys.availability_groups ag ON ag.group_id = r.group_id \
+                     JOIN sys.dm_hadr_availability_replica_states rs ON rs.replica_id = r.replica_id \
+                     WHERE d.name = @P1",
+                    &[&database.name.as_str()],
+                )
+                .await?
+                .into_row()
+                .await
+        })
+        .await?;
+
+    Ok(row.map(|row| ReplicaRole {
+        group: row.get::<&str, _>("group_name").unwrap_or_default().to_string(),
+        is_primary: row.get::<bool, _>("is_primary").unwrap_or(false),
+        is_preferred: row.get::<bool, _>("is_preferred").unwrap_or(false),
+        preference: row.get::<&str, _>("preference").unwrap_or_default().to_string(),
+    }))
+}
+
+/// Lists the online user databases matching the discovery include/exclude
+/// patterns. Every database that is left out is logged with the reason.
+pub async fn discover_databases(conn: &mut Connection, config: &Config) -> Result<Vec<DatabaseConfig>> {
+    let timeouts = conn.timeouts();
+    let client = conn.client().await.map_err(|e| {
+        tracing::error!("Failed to create MSSQL client for discovery: {:?}", e);
         e
     })?;
 
-    let backup_path_str = match backup_path.to_str() {
-        Some(s) => s,
-        None => {
-            let err_msg = format!("Invalid backup path (contains non-UTF8 characters): {:?}", backup_path);
-            tracing::error!("{}", err_msg);
-            bail!(err_msg);
+    // database_id 1-4 are master, tempdb, model and msdb.
+    let rows = timeouts
+        .command("Database discovery", async {
+            client
+                .simple_query(
+                    "SELECT name, state_desc, source_database_id, is_in_standby \
+                     FROM sys.databases WHERE database_id > 4 ORDER BY name",
+                )
+                .await?
+                .into_first_result()
+                .await
+        })
+        .await?;
+
+    let mut databases = Vec::new();
+    for row in rows {
+        let name: &str = row.get("name").unwrap_or_default();
+        let state: &str = row.get("state_desc").unwrap_or_default();
+        let source_database_id: Option<i32> = row.get("source_database_id");
+        let is_in_standby: bool = row.get("is_in_standby").unwrap_or(false);
+
+        let skip_reason = if source_database_id.is_some() {
+            Some("it is a database snapshot".to_string())
+        } else if state != "ONLINE" {
+            Some(format!("its state is {}", state))
+        } else if is_in_standby {
+            Some("it is in STANDBY (read-only restoring)".to_string())
+        } else {
+            discovery_skip_reason(&config.discovery, name)?
+        };
+
+        match skip_reason {
+            Some(reason) => tracing::info!("Discovery: skipping database '{}' because {}.", name, reason),
+            None => {
+                tracing::info!("Discovery: found database '{}'.", name);
+                databases.push(DatabaseConfig::new(name));
+            }
         }
+    }
+
+    Ok(databases)
+}
+
+fn discovery_skip_reason(discovery: &DiscoveryConfig, name: &str) -> Result<Option<String>> {
+    let options = MatchOptions {
+        case_sensitive: false,
+        ..MatchOptions::new()
     };
 
-    let verify_command = format!(
-        "RESTORE VERIFYONLY FROM DISK = N'{}'",
-        backup_path_str
-    );
+    if name.starts_with(crate::restore::VERIFY_DATABASE_PREFIX) {
+        return Ok(Some("it is a restore test scratch database".to_string()));
+    }
+
+    if !discovery.include.is_empty() {
+        let mut included = false;
+        for pattern in &discovery.include {
+            if Pattern::new(pattern)?.matches_with(name, options) {
+                included = true;
+                break;
+            }
+        }
+        if !included {
+            return Ok(Some("it matches no include pattern".to_string()));
+        }
+    }
+
+    for pattern in &discovery.exclude {
+        if Pattern::new(pattern)?.matches_with(name, options) {
+            return Ok(Some(format!("it matches exclude pattern '{}'", pattern)));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Runs `RESTORE VERIFYONLY`, adding `WITH CHECKSUM` when the backup was taken with checksums.
+pub async fn verify_backup(conn: &mut Connection, location: &BackupLocation, checksum: bool) -> Result<()> {
+    let timeouts = conn.timeouts();
+    let client = conn.client().await.map_err(|e| {
+        tracing::error!("Failed to create MSSQL client for verification: {:?}", e);
+        e
+    })?;
+
+    let mut options = WithOptions::new();
+    if checksum {
+        options.flag("CHECKSUM");
+    }
+    let verify_command = sql::restore_verifyonly(&location.server, &options);
 
     tracing::info!("Verifying backup...");
-    client.execute(verify_command, &[]).await.map_err(|e| {
+    let file_size = std::fs::metadata(&location.agent).ok().map(|m| m.len());
+    let verify = progress::track("Verifying backup", &location.agent, file_size, client.execute(verify_command, &[]));
+    let result = timeouts.long_command("RESTORE VERIFYONLY", verify).await;
+    result.map_err(|e| {
         tracing::error!("Backup verification failed: {:?}", e);
         e
     })?;
@@ -80,9 +582,169 @@ pub async fn verify_backup(config: &Config, backup_path: &Path) -> Result<()> {
     Ok(())
 }
 
-async fn create_mssql_client(
-    config: &Config,
-) -> Result<Client<tokio_util::compat::Compat<tokio::net::TcpStream>>> {
+/// The TLS mode is logged at info level on the first connection only, not
+/// on every reconnect.
+static TLS_MODE_LOGGED: AtomicBool = AtomicBool::new(false);
+
+fn configure_tls(t_config: &mut TiberiusConfig, tls: &TlsConfig, host: &str) -> Result<()> {
+    t_config.encryption(match tls.encryption {
+        Encryption::Off => EncryptionLevel::Off,
+        Encryption::On => EncryptionLevel::On,
+        Encryption::Required => EncryptionLevel::Required,
+    });
+
+    // The TLS backend checks the hostname whenever it validates the
+    // certificate, so both are on or off together.
+    let trust = match (&tls.ca_file, tls.trust_server_certificate) {
+        (Some(_), true) => bail!("mssql.tls: ca_file and trust_server_certificate are mutually exclusive"),
+        (Some(ca_file), false) => {
+            if !Path::new(ca_file).is_file() {
+                bail!("mssql.tls: CA certificate file {} not found", ca_file);
+            }
+            t_config.trust_cert_ca(ca_file);
+            format!("certificate validated against {} and the system store", ca_file)
+        }
+        (None, true) => {
+            t_config.trust_cert();
+            "server certificate NOT validated (trust_server_certificate)".to_string()
+        }
+        (None, false) => "certificate validated against the system store".to_string(),
+    };
+
+    let hostname = if tls.trust_server_certificate { "not verified" } else { "verified" };
+    if TLS_MODE_LOGGED.swap(true, Ordering::Relaxed) {
+        tracing::debug!("TLS to {}: encryption {:?}, {}, hostname {}", host, tls.encryption, trust, hostname);
+    } else if tls.trust_server_certificate {
+        tracing::warn!("TLS to {}: encryption {:?}, {}, hostname {}", host, tls.encryption, trust, hostname);
+    } else {
+        tracing::info!("TLS to {}: encryption {:?}, {}, hostname {}", host, tls.encryption, trust, hostname);
+    }
+    Ok(())
+}
+
+/// The value of the first of `keys` present in an ADO.NET connection string.
+fn connection_string_value<'a>(connection_string: &'a str, keys: &[&str]) -> Option<&'a str> {
+    connection_string
+        .split(';')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| keys.contains(&key.trim().to_lowercase().as_str()))
+        .map(|(_, value)| value.trim())
+}
+
+/// Whether an ADO.NET connection string sets its own TLS options.
+fn sets_tls(connection_string: &str) -> bool {
+    connection_string_value(connection_string, &["encrypt", "trustservercertificate", "trustservercertificateca"])
+        .is_some()
+}
+
+/// Whether the login will use Kerberos authentication.
+#[cfg(unix)]
+fn uses_integrated_auth(mssql: &MssqlConfig) -> bool {
+    match non_empty(&mssql.connection_string) {
+        Some(_) if non_empty(&mssql.user).is_some() && mssql.pass.is_some() => false,
+        Some(connection_string) => {
+            connection_string_value(connection_string, &["integrated security", "integratedsecurity"])
+                .is_some_and(|value| matches!(value.to_lowercase().as_str(), "sspi" | "true" | "yes"))
+        }
+        None => mssql.user.is_none() || mssql.pass.is_none(),
+    }
+}
+
+/// `Client::connect` logs in to the SPN `MSSQLSvc/<host>:<port>` of its
+/// config, so an SPN override replaces the host and port for the login only,
+/// after the TCP connection is open. The TLS hostname check then uses the
+/// SPN's host as well.
+fn login_config(mut t_config: TiberiusConfig, spn: Option<&str>) -> Result<TiberiusConfig> {
+    let Some(spn) = spn else {
+        return Ok(t_config);
+    };
+    let target = spn
+        .get(..9)
+        .filter(|prefix| prefix.eq_ignore_ascii_case("MSSQLSvc/"))
+        .map(|_| &spn[9..])
+        .ok_or_else(|| anyhow!("mssql.kerberos.spn must have the form MSSQLSvc/host:port"))?;
+    let (host, port) = match target.split_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse()
+                .map_err(|_| anyhow!("mssql.kerberos.spn must name a port, not an instance: {}", spn))?,
+        ),
+        None => (target, 1433),
+    };
+    t_config.host(host);
+    t_config.port(port);
+    Ok(t_config)
+}
+
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().filter(|value| !value.is_empty())
+}
+
+/// Parses `mssql.connection_string`; `host`, `port`, `instance_name` and
+/// `user`/`pass` override the values in it when set. `[mssql.tls]` applies
+/// only if the string has no TLS options of its own.
+fn connection_string_config(mssql: &MssqlConfig, connection_string: &str) -> Result<TiberiusConfig> {
+    let mut t_config =
+        TiberiusConfig::from_ado_string(connection_string).context("mssql.connection_string is invalid")?;
+
+    if let Some(host) = non_empty(&mssql.host) {
+        t_config.host(host);
+    }
+    if let Some(port) = mssql.port {
+        t_config.port(port);
+    }
+    if let Some(instance) = non_empty(&mssql.instance_name) {
+        t_config.instance_name(instance);
+    }
+    if let (Some(user), Some(pass)) = (non_empty(&mssql.user), &mssql.pass) {
+        t_config.authentication(AuthMethod::sql_server(user, pass));
+    }
+
+    if sets_tls(connection_string) {
+        tracing::debug!("Using the TLS options of mssql.connection_string; [mssql.tls] is ignored.");
+    } else {
+        let addr = t_config.get_addr();
+        configure_tls(&mut t_config, &mssql.tls, &addr)?;
+    }
+    Ok(t_config)
+}
+
+async fn connect_direct(t_config: TiberiusConfig, target: &str, mssql: &MssqlConfig) -> Result<MssqlClient> {
+    let settings = &mssql.connection;
+    let tcp = connection::timeout(settings.connect_timeout(), "TCP connect", TcpStream::connect_named(&t_config))
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to connect to {}: {:?}", target, e);
+            e
+        })?;
+
+    tcp.set_nodelay(true)?;
+    let t_config = login_config(t_config, mssql.kerberos.spn.as_deref())?;
+    let client = connection::timeout(settings.login_timeout(), "Login", Client::connect(t_config, tcp.compat()))
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to establish client connection to {}: {:?}", target, e);
+            e
+        })?;
+
+    tracing::info!("Direct connection successful.");
+    Ok(client)
+}
+
+pub(crate) async fn create_mssql_client(config: &Config) -> Result<MssqlClient> {
+    let settings = &config.mssql.connection;
+    #[cfg(unix)]
+    if uses_integrated_auth(&config.mssql) {
+        kerberos::ensure_ticket(&config.mssql.kerberos).await?;
+    }
+
+    if let Some(connection_string) = non_empty(&config.mssql.connection_string) {
+        let t_config = connection_string_config(&config.mssql, connection_string)?;
+        let target = t_config.get_addr();
+        tracing::info!("Attempting connection to {} from the connection string", target);
+        return connect_direct(t_config, &target, &config.mssql).await;
+    }
+
     let mut t_config = TiberiusConfig::new();
 
     if let (Some(user), Some(pass)) = (&config.mssql.user, &config.mssql.pass) {
@@ -90,34 +752,18 @@ async fn create_mssql_client(
     } else {
         t_config.authentication(AuthMethod::Integrated);
     }
-    //t_config.encryption(EncryptionLevel::Off); // Changed from Required to Off for local dev
-    t_config.trust_cert(); // Use for development; configure properly for production
 
     let host = match &config.mssql.host {
         Some(h) => h.clone(),
         None => hostname::get()?.to_string_lossy().into_owned(),
     };
     t_config.host(&host);
+    configure_tls(&mut t_config, &config.mssql.tls, &host)?;
 
     if let Some(port) = config.mssql.port {
         t_config.port(port);
         tracing::info!("Attempting direct connection to {}:{}", host, port);
-
-        let tcp = TcpStream::connect_named(&t_config).await
-            .map_err(|e| {
-                tracing::error!("Failed to connect to {}:{}: {:?}", host, port, e);
-                e
-            })?;
-
-        tcp.set_nodelay(true)?;
-        let client = Client::connect(t_config, tcp.compat()).await
-            .map_err(|e| {
-                tracing::error!("Failed to establish client connection to {}:{}: {:?}", host, port, e);
-                e
-            })?;
-
-        tracing::info!("Direct connection successful.");
-        return Ok(client);
+        return connect_direct(t_config, &format!("{}:{}", host, port), &config.mssql).await;
     }
 
     let instances_to_try: Vec<String> = match &config.mssql.instance_name {
@@ -125,6 +771,7 @@ async fn create_mssql_client(
         None => vec!["MSSQLSERVER".to_string(), "SQLEXPRESS".to_string()],
     };
 
+    let mut last_error = None;
     for instance in instances_to_try {
         let mut conn_config = t_config.clone();
         conn_config.instance_name(&instance);
@@ -134,10 +781,12 @@ async fn create_mssql_client(
             host
         );
 
-        match TcpStream::connect_named(&conn_config).await {
+        match connection::timeout(settings.connect_timeout(), "TCP connect", TcpStream::connect_named(&conn_config)).await {
             Ok(tcp) => {
                 tcp.set_nodelay(true)?;
-                let client = Client::connect(conn_config, tcp.compat()).await
+                let conn_config = login_config(conn_config, config.mssql.kerberos.spn.as_deref())?;
+                let client = connection::timeout(settings.login_timeout(), "Login", Client::connect(conn_config, tcp.compat()))
+                    .await
                     .map_err(|e| {
                         tracing::error!("Failed to establish client connection to instance '{}': {:?}", instance, e);
                         e
@@ -148,6 +797,7 @@ async fn create_mssql_client(
             }
             Err(e) => {
                 tracing::warn!("Failed to connect to instance '{}' on host '{}': {}", instance, host, e);
+                last_error = Some(e);
                 continue;
             }
         }
@@ -155,5 +805,9 @@ async fn create_mssql_client(
 
     let err_msg = format!("Could not connect to any MSSQL instance on host '{}'", host);
     tracing::error!("{}", err_msg);
-    bail!(err_msg)
+    // Keep the cause so transient network errors are retried.
+    match last_error {
+        Some(e) => Err(e.context(err_msg)),
+        None => bail!(err_msg),
+    }
 }
@@ -1,20 +1,31 @@
+use crate::state;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use anyhow::Result;
 
-pub async fn cleanup_task(temp_path: String) {
+pub async fn cleanup_task(temp_paths: Vec<String>) {
     loop {
         tracing::info!("Running cleanup task...");
-        if let Err(e) = cleanup_old_files(&temp_path) {
-            tracing::error!("Cleanup task failed: {}", e);
+        // Interrupted uploads are resumed from these files; without the
+        // list, nothing is deleted.
+        match state::pending_uploads().await {
+            Ok(pending) => {
+                let pending: Vec<PathBuf> = pending.into_iter().map(|upload| upload.path).collect();
+                for temp_path in &temp_paths {
+                    if let Err(e) = cleanup_old_files(temp_path, &pending) {
+                        tracing::error!("Cleanup task failed for {}: {}", temp_path, e);
+                    }
+                }
+            }
+            Err(e) => tracing::error!("Cleanup task skipped: {:?}", e),
         }
         // Run cleanup every 6 hours
         tokio::time::sleep(Duration::from_secs(6 * 60 * 60)).await;
     }
 }
 
-fn cleanup_old_files(temp_path: &str) -> Result<()> {
+fn cleanup_old_files(temp_path: &str, pending: &[PathBuf]) -> Result<()> {
     let path = Path::new(temp_path);
     if !path.exists() {
         return Ok(());
@@ -23,7 +34,8 @@ fn cleanup_old_files(temp_path: &str) -> Result<()> {
     for entry in fs::read_dir(path)? {
         let entry = entry?;
         let path = entry.path();
-        if path.is_file() {
+        let agent_file = path.file_name().and_then(|name| name.to_str()).is_some_and(is_agent_file);
+        if agent_file && path.is_file() && !pending.contains(&path) {
             let metadata = fs::metadata(&path)?;
             if let Ok(modified) = metadata.modified() {
                 if modified.elapsed()? > Duration::from_secs(24 * 60 * 60) {
@@ -35,3 +47,64 @@ fn cleanup_old_files(temp_path: &str) -> Result<()> {
     }
     Ok(())
 }
+
+/// Whether `name` is a file the agent writes: `<database>_<YYYYMMDD_HHMMSS>`
+/// with `_diff` or `_log` and `.bak` or `.trn`, or `restore_<id>.bak`, each
+/// possibly with `.zst`/`.enc`. Backup directories can be shared with other
+/// tools, whose files must be left alone.
+fn is_agent_file(name: &str) -> bool {
+    let name = name.strip_suffix(".enc").unwrap_or(name);
+    let name = name.strip_suffix(".zst").unwrap_or(name);
+    if let Some(id) = name.strip_prefix("restore_").and_then(|rest| rest.strip_suffix(".bak")) {
+        return !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit());
+    }
+    let Some(stem) = name.strip_suffix(".bak").or_else(|| name.strip_suffix(".trn")) else {
+        return false;
+    };
+    let stem = stem.strip_suffix("_diff").or_else(|| stem.strip_suffix("_log")).unwrap_or(stem);
+    let Some((database, timestamp)) = stem.len().checked_sub(16).and_then(|split| stem.split_at_checked(split)) else {
+        return false;
+    };
+    let timestamp = timestamp.as_bytes();
+    !database.is_empty()
+        && timestamp[0] == b'_'
+        && timestamp[9] == b'_'
+        && timestamp.iter().enumerate().all(|(i, b)| i == 0 || i == 9 || b.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_agent_files() {
+        for name in [
+            "Sales_20261017_020000.bak",
+            "Sales_20261017_020000_diff.bak",
+            "Sales_20261017_021500_log.trn",
+            "Sales_20261017_020000.bak.zst",
+            "Sales_20261017_020000.bak.zst.enc",
+            "Sales_20261017_021500_log.trn.enc",
+            "restore_42.bak",
+            "restore_42.bak.zst.enc",
+        ] {
+            assert!(is_agent_file(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn leaves_other_files_alone() {
+        for name in [
+            "Sales_backup_2026_10_17_020000_1234567.bak",
+            "Sales_20261017.bak",
+            "Sales_20261017_020000.bak.old",
+            "_20261017_020000.bak",
+            "restore_.bak",
+            "restore_latest.bak",
+            "notes.txt",
+            "Sales_20261017_0200ab.trn",
+        ] {
+            assert!(!is_agent_file(name), "{}", name);
+        }
+    }
+}
@@ -1,22 +1,138 @@
 use serde::Deserialize;
 use std::fs;
-use anyhow::Result;
+use std::time::Duration;
+use anyhow::{anyhow, bail, Context, Result};
 
 #[derive(Deserialize, serde::Serialize, Debug, Default, Clone)]
 pub struct Config {
     pub mssql: MssqlConfig,
     pub api: ApiConfig,
+    #[serde(default)]
+    pub upload: UploadConfig,
     pub backup: BackupConfig,
+    #[serde(default)]
+    pub discovery: DiscoveryConfig,
+    #[serde(default)]
+    pub history: HistoryConfig,
+    #[serde(default)]
+    pub databases: Vec<DatabaseConfig>,
 }
 
 #[derive(Deserialize, serde::Serialize, Debug, Default, Clone)]
 pub struct MssqlConfig {
+    /// ADO.NET connection string, e.g. `Server=tcp:sql01,1433;Integrated Security=SSPI;Encrypt=True`.
+    /// The fields below override it when set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connection_string: Option<String>,
     pub host: Option<String>,
     pub port: Option<u16>,
     pub user: Option<String>,
     pub pass: Option<String>,
-    pub database: String,
+    /// Legacy single-database setting; prefer `[[databases]]` entries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database: Option<String>,
     pub instance_name: Option<String>,
+    #[serde(default)]
+    pub tls: TlsConfig,
+    #[serde(default)]
+    pub connection: ConnectionConfig,
+    #[serde(default)]
+    pub kerberos: KerberosConfig,
+}
+
+/// Integrated authentication on Linux, used when `user` and `pass` are unset.
+#[derive(Deserialize, serde::Serialize, Debug, Default, Clone)]
+pub struct KerberosConfig {
+    /// Service account principal, e.g. `svc_backup@EXAMPLE.COM`.
+    pub principal: Option<String>,
+    /// Keytab of `principal`. When set, the agent gets and renews its own
+    /// tickets with `kinit`.
+    pub keytab: Option<String>,
+    /// Server principal to log in to, `MSSQLSvc/host:port`, when it differs
+    /// from the connected host and port (e.g. a named instance).
+    pub spn: Option<String>,
+    /// How often the ticket is renewed from the keytab (default 60).
+    pub renew_interval_minutes: Option<u32>,
+}
+
+/// Timeouts and retries of the SQL Server connection. Unset fields use the
+/// defaults noted below.
+#[derive(Deserialize, serde::Serialize, Debug, Default, Clone)]
+pub struct ConnectionConfig {
+    /// TCP connect, including the SQL Browser lookup (default 15).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_timeout_secs: Option<u64>,
+    /// TLS handshake and login (default 30).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub login_timeout_secs: Option<u64>,
+    /// Reconnect attempts after a transient failure (default 3).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+    /// Delay before the first retry, doubled for each further one (default 2).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_backoff_secs: Option<u64>,
+    /// Metadata queries and other short statements (default 300).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command_timeout_secs: Option<u64>,
+    /// BACKUP, RESTORE and DBCC CHECKDB (default 86400, one day).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub long_command_timeout_secs: Option<u64>,
+}
+
+impl ConnectionConfig {
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_secs(self.connect_timeout_secs.unwrap_or(15))
+    }
+
+    pub fn login_timeout(&self) -> Duration {
+        Duration::from_secs(self.login_timeout_secs.unwrap_or(30))
+    }
+
+    pub fn retries(&self) -> u32 {
+        self.retries.unwrap_or(3)
+    }
+
+    pub fn retry_backoff(&self) -> Duration {
+        Duration::from_secs(self.retry_backoff_secs.unwrap_or(2))
+    }
+
+    pub fn command_timeout(&self) -> Duration {
+        Duration::from_secs(self.command_timeout_secs.unwrap_or(300))
+    }
+
+    pub fn long_command_timeout(&self) -> Duration {
+        Duration::from_secs(self.long_command_timeout_secs.unwrap_or(24 * 60 * 60))
+    }
+}
+
+/// TLS settings of the SQL Server connection. The certificate's hostname is
+/// checked whenever the certificate is validated. Unknown keys are rejected,
+/// so a setting the agent doesn't support isn't silently ignored.
+#[derive(Deserialize, serde::Serialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    #[serde(default)]
+    pub encryption: Encryption,
+    /// CA certificate (PEM or DER) to trust in addition to the system store,
+    /// e.g. for a server with a self-signed certificate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_file: Option<String>,
+    /// Accept any server certificate without validating it. Only for testing;
+    /// prefer `ca_file`.
+    #[serde(default)]
+    pub trust_server_certificate: bool,
+}
+
+#[derive(Deserialize, serde::Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Encryption {
+    /// Only the login packet is encrypted.
+    Off,
+    /// Encrypt everything if the server supports it.
+    On,
+    /// Encrypt everything; fail if the server can't.
+    #[default]
+    Required,
 }
 
 #[derive(Deserialize, serde::Serialize, Debug, Default, Clone)]
@@ -24,16 +140,383 @@ pub struct ApiConfig {
     pub url: String,
     pub server_token: String,
     pub auth_token: String,
+    /// Size of each upload request (default 8 MB). A dropped connection
+    /// costs at most one chunk.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_size_mb: Option<u32>,
+}
+
+impl ApiConfig {
+    pub fn chunk_size_bytes(&self) -> u64 {
+        u64::from(self.chunk_size_mb.unwrap_or(8).clamp(1, 64)) * 1024 * 1024
+    }
+}
+
+#[derive(Deserialize, serde::Serialize, Debug, Default, Clone)]
+pub struct UploadConfig {
+    #[serde(default)]
+    pub compression: CompressionConfig,
+    #[serde(default)]
+    pub encryption: EncryptionConfig,
+    #[serde(default)]
+    pub throttle: ThrottleConfig,
+}
+
+/// zstd compression of backup files before they are uploaded.
+#[derive(Deserialize, serde::Serialize, Debug, Default, Clone)]
+pub struct CompressionConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// zstd level from 1 to 19 (default 3).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<i32>,
+    /// Worker threads; 0 (the default) compresses on one thread.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threads: Option<u32>,
+}
+
+impl CompressionConfig {
+    pub fn level(&self) -> i32 {
+        self.level.unwrap_or(3).clamp(1, 19)
+    }
+
+    pub fn threads(&self) -> u32 {
+        self.threads.unwrap_or(0)
+    }
+}
+
+/// Encryption of backup files before they are uploaded, so the API only
+/// stores ciphertext.
+#[derive(Deserialize, serde::Serialize, Debug, Default, Clone)]
+pub struct EncryptionConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// The key new backups are encrypted with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+    /// Every key, including retired ones still needed to restore older backups.
+    #[serde(default)]
+    pub keys: Vec<EncryptionKey>,
+}
+
+impl EncryptionConfig {
+    pub fn current_key(&self) -> Result<&EncryptionKey> {
+        let id = self
+            .key_id
+            .as_deref()
+            .ok_or_else(|| anyhow!("upload.encryption.key_id is not set"))?;
+        self.key(id)
+    }
+
+    pub fn key(&self, id: &str) -> Result<&EncryptionKey> {
+        self.keys
+            .iter()
+            .find(|key| key.id == id)
+            .ok_or_else(|| anyhow!("Encryption key '{}' is not in upload.encryption.keys", id))
+    }
+}
+
+/// A passphrase the backup keys are derived from, read from a file or an
+/// environment variable rather than stored in the config.
+#[derive(Deserialize, serde::Serialize, Debug, Default, Clone)]
+pub struct EncryptionKey {
+    /// Recorded with each backup, so the key can be found again after a rotation.
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passphrase_file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passphrase_env: Option<String>,
+}
+
+impl EncryptionKey {
+    pub fn passphrase(&self) -> Result<String> {
+        let passphrase = match (&self.passphrase_file, &self.passphrase_env) {
+            (Some(path), _) => fs::read_to_string(path)
+                .with_context(|| format!("Failed to read the passphrase of key '{}' from {}", self.id, path))?
+                .trim_end_matches(['\r', '\n'])
+                .to_string(),
+            (None, Some(name)) => std::env::var(name)
+                .with_context(|| format!("Failed to read the passphrase of key '{}' from ${}", self.id, name))?,
+            (None, None) => bail!("Key '{}' has neither passphrase_file nor passphrase_env", self.id),
+        };
+        if passphrase.is_empty() {
+            bail!("The passphrase of key '{}' is empty", self.id);
+        }
+        Ok(passphrase)
+    }
+}
+
+/// Upload bandwidth limit by time of day. Edits to config.toml apply within
+/// seconds, also to uploads already running.
+#[derive(Deserialize, serde::Serialize, Debug, Default, Clone)]
+pub struct ThrottleConfig {
+    /// In MB/s, outside the windows below. Unset means unlimited.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit_mb_per_sec: Option<f64>,
+    /// The first window containing the current local time sets the limit.
+    #[serde(default)]
+    pub windows: Vec<ThrottleWindow>,
+}
+
+/// A daily period with its own limit, e.g. 22:00 to 06:00.
+#[derive(Deserialize, serde::Serialize, Debug, Default, Clone)]
+pub struct ThrottleWindow {
+    /// `HH:MM`, local time.
+    pub start: String,
+    pub end: String,
+    /// In MB/s. Unset means unlimited.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit_mb_per_sec: Option<f64>,
 }
 
 #[derive(Deserialize, serde::Serialize, Debug, Default, Clone)]
 pub struct BackupConfig {
     pub temp_path: String,
+    /// Directory SQL Server writes backups to, as the server sees it, e.g.
+    /// `/var/opt/mssql/backup/orka` in a container or `D:\Backups\Orka` on a
+    /// remote host; a directory of the agent's own, not the server's default.
+    /// Leave unset when the agent runs on the SQL Server host.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_backup_dir: Option<String>,
+    /// The same directory as the agent sees it, e.g. `\\sqlhost\Backups\Orka` or
+    /// the host path of the container's volume. Defaults to `temp_path`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent_read_dir: Option<String>,
+    #[serde(default)]
+    pub schedule: ScheduleConfig,
+    #[serde(default)]
+    pub options: BackupOptions,
+    #[serde(default)]
+    pub verification: Verification,
+}
+
+impl BackupConfig {
+    /// The backup directory for `temp_path` as the server and the agent see it.
+    pub fn dirs(&self, temp_path: &str) -> BackupDirs {
+        match &self.server_backup_dir {
+            Some(server) => BackupDirs {
+                server: server.clone(),
+                agent: self.agent_read_dir.clone().unwrap_or_else(|| temp_path.to_string()),
+            },
+            None => BackupDirs {
+                server: temp_path.to_string(),
+                agent: temp_path.to_string(),
+            },
+        }
+    }
+}
+
+/// One directory under the two names it has when SQL Server runs on another
+/// host or in a container; both are the same when the agent runs on the host.
+#[derive(Debug, Clone)]
+pub struct BackupDirs {
+    pub server: String,
+    pub agent: String,
+}
+
+/// How a backup is checked before it is uploaded.
+#[derive(Deserialize, serde::Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Verification {
+    /// `RESTORE VERIFYONLY`: the backup media is complete and readable.
+    #[default]
+    Media,
+    /// Additionally restore full backups into a scratch database, run
+    /// `DBCC CHECKDB` on it and drop it again.
+    RestoreTest,
+}
+
+/// Options of the `BACKUP` statement. Unset fields are left to the server default.
+#[derive(Deserialize, serde::Serialize, Debug, Default, Clone)]
+pub struct BackupOptions {
+    /// Native backup compression; ignored on editions without it (Express, Web).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<bool>,
+    /// `WITH CHECKSUM`; verification then also checks the page checksums.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<bool>,
+    /// `CONTINUE_AFTER_ERROR` when true, `STOP_ON_ERROR` when false.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub continue_after_error: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub buffer_count: Option<u32>,
+    /// In bytes; a multiple of 64 KB up to 4 MB.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_transfer_size: Option<u32>,
+    /// In bytes; a power of two from 512 to 65536.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_size: Option<u32>,
+}
+
+impl BackupOptions {
+    /// Returns these options with every field set in `overrides` replaced.
+    pub fn merged(&self, overrides: Option<&BackupOptions>) -> BackupOptions {
+        let Some(o) = overrides else {
+            return self.clone();
+        };
+        BackupOptions {
+            compression: o.compression.or(self.compression),
+            checksum: o.checksum.or(self.checksum),
+            continue_after_error: o.continue_after_error.or(self.continue_after_error),
+            buffer_count: o.buffer_count.or(self.buffer_count),
+            max_transfer_size: o.max_transfer_size.or(self.max_transfer_size),
+            block_size: o.block_size.or(self.block_size),
+        }
+    }
+}
+
+/// Full/differential cadence, e.g. a weekly full with daily differentials.
+#[derive(Deserialize, serde::Serialize, Debug, Default, Clone)]
+pub struct ScheduleConfig {
+    /// Take differential backups between full backups. When false every
+    /// cycle takes a full backup.
+    #[serde(default)]
+    pub differential: bool,
+    /// Take a full backup once the last one is this many days old (default 7).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub full_interval_days: Option<u32>,
+    /// Take the full backup on this weekday, e.g. "sunday".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub full_weekday: Option<String>,
+    /// Take a transaction log backup this often for databases in FULL or
+    /// BULK_LOGGED recovery. Unset disables log backups.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_interval_minutes: Option<u32>,
+}
+
+/// Automatic discovery of databases from `sys.databases` at the start of each cycle.
+#[derive(Deserialize, serde::Serialize, Debug, Default, Clone)]
+pub struct DiscoveryConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Glob patterns (e.g. `Prod_*`); an empty list includes every user database.
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Glob patterns (e.g. `*_Staging`) excluded even when they match `include`.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+/// Periodic check of the `msdb` backup history for backups taken by other
+/// tools or people.
+#[derive(Deserialize, serde::Serialize, Debug, Default, Clone)]
+pub struct HistoryConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// How often to check (default 60).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval_minutes: Option<u32>,
+    /// Also send the findings to the API.
+    #[serde(default)]
+    pub report_to_api: bool,
+}
+
+#[derive(Deserialize, serde::Serialize, Debug, Default, Clone)]
+pub struct DatabaseConfig {
+    pub name: String,
+    /// Overrides `backup.temp_path` for this database. Not supported with
+    /// `backup.server_backup_dir`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temp_path: Option<String>,
+    /// Overrides `backup.schedule` for this database.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<ScheduleConfig>,
+    /// The database is backed up by another tool (e.g. a Maintenance Plan);
+    /// the agent only keeps an off-site copy and defaults to `copy_only`.
+    #[serde(default)]
+    pub managed_elsewhere: bool,
+    /// Take full and log backups `WITH COPY_ONLY` so they don't reset the
+    /// differential base or truncate the log of an existing backup chain.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub copy_only: Option<bool>,
+    /// Overrides individual fields of `backup.options` for this database.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub options: Option<BackupOptions>,
+    /// Overrides `backup.verification` for this database.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verification: Option<Verification>,
+}
+
+impl DatabaseConfig {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn temp_path<'a>(&'a self, backup: &'a BackupConfig) -> &'a str {
+        self.temp_path.as_deref().unwrap_or(&backup.temp_path)
+    }
+
+    pub fn backup_dirs(&self, backup: &BackupConfig) -> BackupDirs {
+        backup.dirs(self.temp_path(backup))
+    }
+
+    pub fn copy_only(&self) -> bool {
+        self.copy_only.unwrap_or(self.managed_elsewhere)
+    }
+
+    pub fn backup_options(&self, backup: &BackupConfig) -> BackupOptions {
+        backup.options.merged(self.options.as_ref())
+    }
+
+    pub fn verification(&self, backup: &BackupConfig) -> Verification {
+        self.verification.unwrap_or(backup.verification)
+    }
+
+    pub fn schedule<'a>(&'a self, backup: &'a BackupConfig) -> &'a ScheduleConfig {
+        self.schedule.as_ref().unwrap_or(&backup.schedule)
+    }
+}
+
+impl Config {
+    /// All databases to back up: the `[[databases]]` entries followed by the
+    /// legacy `mssql.database`, if set and not already listed.
+    pub fn database_entries(&self) -> Vec<DatabaseConfig> {
+        let mut entries = self.databases.clone();
+        if let Some(name) = self.mssql.database.as_deref().filter(|n| !n.is_empty()) {
+            if !entries.iter().any(|d| d.name.eq_ignore_ascii_case(name)) {
+                entries.push(DatabaseConfig::new(name));
+            }
+        }
+        entries
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.mssql.tls.ca_file.is_some() && self.mssql.tls.trust_server_certificate {
+            bail!("mssql.tls: ca_file and trust_server_certificate are mutually exclusive");
+        }
+        // server_backup_dir names one directory on the server; a database
+        // writing elsewhere would need its own server-side path as well.
+        if self.backup.server_backup_dir.is_some() {
+            if let Some(database) = self.databases.iter().find(|d| d.temp_path.is_some()) {
+                bail!(
+                    "Database '{}' sets temp_path, which is not supported together with backup.server_backup_dir",
+                    database.name
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Every directory backups are written to, for the cleanup task.
+    pub fn temp_paths(&self) -> Vec<String> {
+        let mut paths = vec![self.backup.dirs(&self.backup.temp_path).agent];
+        for database in self.database_entries() {
+            let path = database.backup_dirs(&self.backup).agent;
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+        paths
+    }
 }
 
 pub fn load_config(path: &str) -> Result<Config> {
     let content = fs::read_to_string(path)?;
     let config: Config = toml::from_str(&content)?;
+    config.validate()?;
     Ok(config)
 }
 
@@ -0,0 +1,223 @@
+use crate::backup::{self, MssqlClient};
+use crate::config::Config;
+use anyhow::Result;
+use std::fmt;
+use std::future::Future;
+use std::io::ErrorKind;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// A session idle for longer than this is checked with `SELECT 1` before reuse.
+const KEEPALIVE_IDLE: Duration = Duration::from_secs(60);
+const KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// SQL Server errors worth retrying: Azure SQL throttling and failover.
+/// Dropped connections surface as I/O errors instead.
+const TRANSIENT_SERVER_ERRORS: [u32; 5] = [10928, 10929, 40197, 40501, 40613];
+
+/// I/O errors of a connection the network or the server dropped, as opposed
+/// to local failures such as a missing file or program.
+const TRANSIENT_IO_ERRORS: [ErrorKind; 7] = [
+    ErrorKind::ConnectionRefused,
+    ErrorKind::ConnectionReset,
+    ErrorKind::ConnectionAborted,
+    ErrorKind::NotConnected,
+    ErrorKind::BrokenPipe,
+    ErrorKind::TimedOut,
+    ErrorKind::UnexpectedEof,
+];
+
+/// An operation that did not finish within its timeout.
+#[derive(Debug)]
+pub struct TimedOut {
+    pub operation: &'static str,
+    pub after: Duration,
+}
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} timed out after {} s", self.operation, self.after.as_secs())
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// Awaits `future`, failing with [`TimedOut`] once `limit` has passed.
+pub async fn timeout<T, E>(limit: Duration, operation: &'static str, future: impl Future<Output = Result<T, E>>) -> Result<T>
+where
+    E: Into<anyhow::Error>,
+{
+    match tokio::time::timeout(limit, future).await {
+        Ok(result) => result.map_err(Into::into),
+        Err(_) => Err(TimedOut { operation, after: limit }.into()),
+    }
+}
+
+/// Whether `error` is a network or server hiccup that a new connection may not hit.
+pub fn is_transient(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        if cause.is::<TimedOut>() {
+            return true;
+        }
+        if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+            return TRANSIENT_IO_ERRORS.contains(&e.kind());
+        }
+        match cause.downcast_ref::<tiberius::error::Error>() {
+            Some(tiberius::error::Error::Io { kind, .. }) => TRANSIENT_IO_ERRORS.contains(kind),
+            Some(tiberius::error::Error::Server(token)) => TRANSIENT_SERVER_ERRORS.contains(&token.code()),
+            _ => false,
+        }
+    })
+}
+
+/// Command timeouts of a [`Connection`]. A command that times out or fails
+/// with a transient error leaves the session in an unknown state, so the
+/// connection reconnects before its next use.
+#[derive(Debug, Clone)]
+pub struct Timeouts {
+    command: Duration,
+    long_command: Duration,
+    broken: Arc<AtomicBool>,
+}
+
+impl Timeouts {
+    /// For metadata queries and other statements that should return quickly.
+    pub async fn command<T, E>(&self, operation: &'static str, future: impl Future<Output = Result<T, E>>) -> Result<T>
+    where
+        E: Into<anyhow::Error>,
+    {
+        self.run(self.command, operation, future).await
+    }
+
+    /// For BACKUP, RESTORE and DBCC, which can run for hours on large databases.
+    pub async fn long_command<T, E>(
+        &self,
+        operation: &'static str,
+        future: impl Future<Output = Result<T, E>>,
+    ) -> Result<T>
+    where
+        E: Into<anyhow::Error>,
+    {
+        self.run(self.long_command, operation, future).await
+    }
+
+    async fn run<T, E>(&self, limit: Duration, operation: &'static str, future: impl Future<Output = Result<T, E>>) -> Result<T>
+    where
+        E: Into<anyhow::Error>,
+    {
+        let result = timeout(limit, operation, future).await;
+        if let Err(e) = &result {
+            if is_transient(e) {
+                self.broken.store(true, Ordering::Relaxed);
+            }
+        }
+        result
+    }
+}
+
+/// One SQL Server session, opened on first use and reused for every
+/// statement of a backup cycle. Reconnects with backoff when the session is
+/// lost.
+pub struct Connection {
+    config: Config,
+    client: Option<MssqlClient>,
+    last_used: Instant,
+    timeouts: Timeouts,
+}
+
+impl Connection {
+    pub fn new(config: &Config) -> Self {
+        let settings = &config.mssql.connection;
+        Self {
+            config: config.clone(),
+            client: None,
+            last_used: Instant::now(),
+            timeouts: Timeouts {
+                command: settings.command_timeout(),
+                long_command: settings.long_command_timeout(),
+                broken: Arc::new(AtomicBool::new(false)),
+            },
+        }
+    }
+
+    pub fn timeouts(&self) -> Timeouts {
+        self.timeouts.clone()
+    }
+
+    /// Returns the session, connecting first if there is none or the current
+    /// one failed its keep-alive check or a command on it failed transiently.
+    pub async fn client(&mut self) -> Result<&mut MssqlClient> {
+        if self.timeouts.broken.swap(false, Ordering::Relaxed) && self.client.take().is_some() {
+            tracing::warn!("Discarding the SQL Server session after a command timed out or lost the connection.");
+        }
+        if self.last_used.elapsed() >= KEEPALIVE_IDLE {
+            if let Some(client) = self.client.as_mut() {
+                let alive = timeout(KEEPALIVE_TIMEOUT, "Keep-alive query", async {
+                    client.simple_query("SELECT 1").await?.into_results().await
+                })
+                .await;
+                if let Err(e) = alive {
+                    tracing::warn!("SQL Server session lost ({:#}); reconnecting.", e);
+                    self.client = None;
+                }
+            }
+        }
+        self.last_used = Instant::now();
+
+        if self.client.is_none() {
+            self.client = Some(self.connect().await?);
+        }
+        Ok(self.client.as_mut().expect("client was just connected"))
+    }
+
+    async fn connect(&self) -> Result<MssqlClient> {
+        let settings = &self.config.mssql.connection;
+        let attempts = settings.retries() + 1;
+        let mut delay = settings.retry_backoff();
+        let mut attempt = 1;
+        loop {
+            match backup::create_mssql_client(&self.config).await {
+                Ok(client) => return Ok(client),
+                Err(e) if attempt < attempts && is_transient(&e) => {
+                    tracing::warn!(
+                        "Connection attempt {}/{} failed: {:#}. Retrying in {} s...",
+                        attempt,
+                        attempts,
+                        e,
+                        delay.as_secs()
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(MAX_RETRY_DELAY);
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    fn io_error(kind: ErrorKind) -> anyhow::Error {
+        Err::<(), _>(std::io::Error::from(kind)).context("Backup failed").unwrap_err()
+    }
+
+    #[test]
+    fn dropped_connections_are_transient() {
+        assert!(is_transient(&io_error(ErrorKind::ConnectionReset)));
+        assert!(is_transient(&io_error(ErrorKind::UnexpectedEof)));
+        assert!(is_transient(&TimedOut { operation: "BACKUP", after: Duration::from_secs(1) }.into()));
+    }
+
+    #[test]
+    fn local_failures_are_not_transient() {
+        assert!(!is_transient(&io_error(ErrorKind::NotFound)));
+        assert!(!is_transient(&io_error(ErrorKind::PermissionDenied)));
+        assert!(!is_transient(&anyhow::anyhow!("Invalid configuration")));
+    }
+}
@@ -0,0 +1,124 @@
+use crate::config::{CompressionConfig, EncryptionConfig, UploadConfig};
+use crate::encryption::{DecryptingReader, EncryptingWriter};
+use anyhow::Result;
+use sha2::digest::{FixedOutput, Update};
+use sha2::Sha256;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Codec name in the backup metadata.
+pub const ZSTD: &str = "zstd";
+
+/// Hashes everything read through it, so a file is read only once.
+struct HashingReader<R, H> {
+    inner: R,
+    hasher: H,
+}
+
+impl<R: Read, H: Update + FixedOutput> HashingReader<R, H> {
+    fn new(inner: R, hasher: H) -> Self {
+        Self { inner, hasher }
+    }
+
+    fn hex(self) -> String {
+        self.hasher.finalize_fixed().iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+impl<R: Read, H: Update> Read for HashingReader<R, H> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let length = self.inner.read(buf)?;
+        self.hasher.update(&buf[..length]);
+        Ok(length)
+    }
+}
+
+/// Where the encoded copy of `path` is written, e.g. `<path>.zst.enc`.
+pub fn encoded_path(path: &Path, compressed: bool, encrypted: bool) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    if compressed {
+        name.push(".zst");
+    }
+    if encrypted {
+        name.push(".enc");
+    }
+    PathBuf::from(name)
+}
+
+/// Compresses and/or encrypts `source` into `target`, as `settings` enable,
+/// and returns the checksum of `source`: its SHA-256, or when encrypted its
+/// HMAC-SHA256 keyed from the data key. The file is read once.
+///
+/// The encoded copy is kept on disk so an interrupted upload resumes on the
+/// very bytes it started with: encrypting again would pick a new data key,
+/// and a changed compression level would give other bytes. Pre-flight
+/// checks the space for both files.
+pub async fn encode_file(source: &Path, target: &Path, settings: &UploadConfig) -> Result<String> {
+    let (source, target, settings) = (source.to_path_buf(), target.to_path_buf(), settings.clone());
+    tokio::task::spawn_blocking(move || {
+        let compression = settings.compression.enabled.then_some(&settings.compression);
+        let source = BufReader::new(File::open(&source)?);
+        let file = BufWriter::new(File::create(&target)?);
+        if settings.encryption.enabled {
+            let writer = EncryptingWriter::new(file, settings.encryption.current_key()?)?;
+            let mut reader = HashingReader::new(source, writer.checksum_mac());
+            compress_into(&mut reader, writer, compression)?.finish()?.flush()?;
+            Ok(reader.hex())
+        } else {
+            let mut reader = HashingReader::new(source, Sha256::default());
+            compress_into(&mut reader, file, compression)?.flush()?;
+            Ok(reader.hex())
+        }
+    })
+    .await?
+}
+
+fn compress_into<W: Write>(reader: &mut impl Read, mut writer: W, settings: Option<&CompressionConfig>) -> io::Result<W> {
+    let Some(settings) = settings else {
+        io::copy(reader, &mut writer)?;
+        return Ok(writer);
+    };
+    let mut encoder = zstd::stream::write::Encoder::new(writer, settings.level())?;
+    if settings.threads() > 0 {
+        encoder.multithread(settings.threads())?;
+    }
+    io::copy(reader, &mut encoder)?;
+    encoder.finish()
+}
+
+/// Decrypts and/or decompresses `source` into `target` and returns the
+/// checksum of the result, as [`encode_file`] does.
+pub async fn decode_file(
+    source: &Path,
+    target: &Path,
+    compressed: bool,
+    encrypted: bool,
+    encryption: &EncryptionConfig,
+) -> Result<String> {
+    let (source, target, encryption) = (source.to_path_buf(), target.to_path_buf(), encryption.clone());
+    tokio::task::spawn_blocking(move || {
+        let mut reader: Box<dyn Read> = Box::new(BufReader::new(File::open(&source)?));
+        let mut checksum_mac = None;
+        if encrypted {
+            let decrypting = DecryptingReader::new(reader, &encryption)?;
+            checksum_mac = Some(decrypting.checksum_mac());
+            reader = Box::new(decrypting);
+        }
+        if compressed {
+            reader = Box::new(zstd::stream::read::Decoder::new(reader)?);
+        }
+        let writer = BufWriter::new(File::create(&target)?);
+        Ok(match checksum_mac {
+            Some(mac) => copy_hashed(HashingReader::new(reader, mac), writer)?,
+            None => copy_hashed(HashingReader::new(reader, Sha256::default()), writer)?,
+        })
+    })
+    .await?
+}
+
+fn copy_hashed<R: Read, H: Update + FixedOutput>(mut reader: HashingReader<R, H>, mut writer: impl Write) -> io::Result<String> {
+    io::copy(&mut reader, &mut writer)?;
+    writer.flush()?;
+    Ok(reader.hex())
+}
@@ -0,0 +1,414 @@
+//! AES-256-GCM encryption of backup files, in 1 MiB chunks so a file of any
+//! size is encrypted and decrypted as a stream.
+//!
+//! Each file is encrypted with a random data key, which is stored in the
+//! header wrapped (AES-256-GCM) by a key derived from the passphrase with
+//! Argon2id. The header:
+//!
+//! | field                        | bytes  |
+//! |------------------------------|--------|
+//! | magic `ORKAENC1`             | 8      |
+//! | key id length, key id        | 1 + n  |
+//! | Argon2id salt                | 16     |
+//! | nonce, wrapped data key      | 12 + 48|
+//! | chunk size, big endian       | 4      |
+//! | nonce prefix                 | 7      |
+//!
+//! Each chunk is sealed with the nonce `prefix || counter (u32 BE) || last`,
+//! `last` being 1 for the final chunk only, and the whole header as associated
+//! data, so reordered, truncated or altered files fail to decrypt.
+//!
+//! The checksum of the plaintext sent with an encrypted backup is an
+//! HMAC-SHA256 keyed from the data key, so the server can't test guesses
+//! about the contents against it.
+
+use crate::config::{EncryptionConfig, EncryptionKey};
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::{anyhow, bail, Result};
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::Sha256;
+use std::io::{self, Read, Write};
+
+/// Algorithm name in the backup metadata.
+pub const ALGORITHM: &str = "aes-256-gcm";
+
+const MAGIC: &[u8; 8] = b"ORKAENC1";
+const CHUNK_SIZE: usize = 1024 * 1024;
+const MAX_CHUNK_SIZE: usize = 64 * 1024 * 1024;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const WRAPPED_KEY_LEN: usize = 32 + TAG_LEN;
+const PREFIX_LEN: usize = 7;
+/// Derives the checksum key from the data key.
+const CHECKSUM_LABEL: &[u8] = b"ORKAENC1 raw checksum";
+
+pub type ChecksumMac = Hmac<Sha256>;
+
+/// Encrypts everything written to it into `inner`. `finish` must be called
+/// to write the last chunk.
+pub struct EncryptingWriter<W: Write> {
+    inner: W,
+    cipher: Aes256Gcm,
+    header: Vec<u8>,
+    nonce_prefix: [u8; PREFIX_LEN],
+    counter: u32,
+    buffer: Vec<u8>,
+    checksum: ChecksumMac,
+}
+
+impl<W: Write> EncryptingWriter<W> {
+    /// Writes the header for a new data key wrapped with `key`.
+    pub fn new(mut inner: W, key: &EncryptionKey) -> Result<Self> {
+        let id_len = u8::try_from(key.id.len()).map_err(|_| anyhow!("Key id '{}' is longer than 255 bytes", key.id))?;
+        let data_key: [u8; 32] = random();
+        let salt: [u8; SALT_LEN] = random();
+        let wrap_nonce: [u8; NONCE_LEN] = random();
+        let nonce_prefix: [u8; PREFIX_LEN] = random();
+        let wrapped = key_cipher(&key.passphrase()?, &salt)?
+            .encrypt(
+                Nonce::from_slice(&wrap_nonce),
+                Payload {
+                    msg: &data_key,
+                    aad: key.id.as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow!("Failed to wrap the data key"))?;
+
+        let mut header = MAGIC.to_vec();
+        header.push(id_len);
+        header.extend_from_slice(key.id.as_bytes());
+        header.extend_from_slice(&salt);
+        header.extend_from_slice(&wrap_nonce);
+        header.extend_from_slice(&wrapped);
+        header.extend_from_slice(&(CHUNK_SIZE as u32).to_be_bytes());
+        header.extend_from_slice(&nonce_prefix);
+        inner.write_all(&header)?;
+
+        Ok(Self {
+            inner,
+            cipher: Aes256Gcm::new(&data_key.into()),
+            header,
+            nonce_prefix,
+            counter: 0,
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+            checksum: checksum_mac(&data_key),
+        })
+    }
+
+    /// The MAC to checksum the plaintext with.
+    pub fn checksum_mac(&self) -> ChecksumMac {
+        self.checksum.clone()
+    }
+
+    /// Seals the last chunk and returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.seal(true)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn seal(&mut self, last: bool) -> io::Result<()> {
+        let sealed = self
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&chunk_nonce(&self.nonce_prefix, self.counter, last)),
+                Payload {
+                    msg: &self.buffer,
+                    aad: &self.header,
+                },
+            )
+            .map_err(|_| io::Error::other("Failed to encrypt a chunk"))?;
+        self.inner.write_all(&sealed)?;
+        self.buffer.clear();
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| io::Error::other("Too many chunks to encrypt"))?;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for EncryptingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A full chunk is sealed only once more data follows: the last chunk
+        // is sealed differently.
+        if self.buffer.len() == CHUNK_SIZE && !buf.is_empty() {
+            self.seal(false)?;
+        }
+        let length = buf.len().min(CHUNK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..length]);
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decrypts a file written by [`EncryptingWriter`], with the key named in
+/// its header.
+pub struct DecryptingReader<R: Read> {
+    inner: R,
+    cipher: Aes256Gcm,
+    header: Vec<u8>,
+    nonce_prefix: [u8; PREFIX_LEN],
+    chunk_size: usize,
+    counter: u32,
+    /// The next sealed chunk, read ahead to know whether it is the last.
+    sealed: Vec<u8>,
+    plain: Vec<u8>,
+    position: usize,
+    done: bool,
+    checksum: ChecksumMac,
+}
+
+impl<R: Read> DecryptingReader<R> {
+    pub fn new(mut inner: R, config: &EncryptionConfig) -> Result<Self> {
+        let mut header = vec![0; MAGIC.len() + 1];
+        inner.read_exact(&mut header)?;
+        if header[..MAGIC.len()] != MAGIC[..] {
+            bail!("The file is not an encrypted backup");
+        }
+        let id_len = header[MAGIC.len()] as usize;
+        header.resize(header.len() + id_len + SALT_LEN + NONCE_LEN + WRAPPED_KEY_LEN + 4 + PREFIX_LEN, 0);
+        inner.read_exact(&mut header[MAGIC.len() + 1..])?;
+
+        let rest = &header[MAGIC.len() + 1..];
+        let (id, rest) = rest.split_at(id_len);
+        let (salt, rest) = rest.split_at(SALT_LEN);
+        let (wrap_nonce, rest) = rest.split_at(NONCE_LEN);
+        let (wrapped, rest) = rest.split_at(WRAPPED_KEY_LEN);
+        let (chunk_size, nonce_prefix) = rest.split_at(4);
+
+        let key_id = std::str::from_utf8(id)?;
+        let key = config.key(key_id)?;
+        let data_key = key_cipher(&key.passphrase()?, salt)?
+            .decrypt(Nonce::from_slice(wrap_nonce), Payload { msg: wrapped, aad: id })
+            .map_err(|_| anyhow!("Key '{}' cannot decrypt this backup: wrong passphrase or damaged file", key_id))?;
+        let chunk_size = u32::from_be_bytes(chunk_size.try_into()?) as usize;
+        if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+            bail!("Invalid chunk size {} in the encrypted backup", chunk_size);
+        }
+
+        let mut reader = Self {
+            cipher: Aes256Gcm::new_from_slice(&data_key).map_err(|_| anyhow!("Invalid data key length"))?,
+            nonce_prefix: nonce_prefix.try_into()?,
+            header,
+            inner,
+            chunk_size,
+            counter: 0,
+            sealed: Vec::new(),
+            plain: Vec::new(),
+            position: 0,
+            done: false,
+            checksum: checksum_mac(&data_key),
+        };
+        reader.sealed = reader.read_sealed()?;
+        Ok(reader)
+    }
+
+    /// The MAC the plaintext was checksummed with.
+    pub fn checksum_mac(&self) -> ChecksumMac {
+        self.checksum.clone()
+    }
+
+    fn read_sealed(&mut self) -> io::Result<Vec<u8>> {
+        let mut sealed = vec![0; self.chunk_size + TAG_LEN];
+        let length = read_full(&mut self.inner, &mut sealed)?;
+        sealed.truncate(length);
+        Ok(sealed)
+    }
+
+    fn open_next(&mut self) -> io::Result<()> {
+        let next = self.read_sealed()?;
+        let last = next.is_empty();
+        self.plain = self
+            .cipher
+            .decrypt(
+                Nonce::from_slice(&chunk_nonce(&self.nonce_prefix, self.counter, last)),
+                Payload {
+                    msg: &self.sealed,
+                    aad: &self.header,
+                },
+            )
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Chunk {} of the encrypted backup is damaged or missing", self.counter),
+                )
+            })?;
+        self.position = 0;
+        self.sealed = next;
+        self.done = last;
+        self.counter = self.counter.wrapping_add(1);
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecryptingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.position < self.plain.len() {
+                let length = buf.len().min(self.plain.len() - self.position);
+                buf[..length].copy_from_slice(&self.plain[self.position..self.position + length]);
+                self.position += length;
+                return Ok(length);
+            }
+            if self.done {
+                return Ok(0);
+            }
+            self.open_next()?;
+        }
+    }
+}
+
+fn key_cipher(passphrase: &str, salt: &[u8]) -> Result<Aes256Gcm> {
+    let mut key = [0; 32];
+    argon2::Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow!("Failed to derive the key: {}", e))?;
+    Ok(Aes256Gcm::new(&key.into()))
+}
+
+fn checksum_mac(data_key: &[u8]) -> ChecksumMac {
+    let key = <ChecksumMac as Mac>::new_from_slice(data_key)
+        .expect("HMAC accepts keys of any length")
+        .chain_update(CHECKSUM_LABEL)
+        .finalize()
+        .into_bytes();
+    <ChecksumMac as Mac>::new_from_slice(&key).expect("HMAC accepts keys of any length")
+}
+
+fn chunk_nonce(prefix: &[u8; PREFIX_LEN], counter: u32, last: bool) -> [u8; NONCE_LEN] {
+    let mut nonce = [0; NONCE_LEN];
+    nonce[..PREFIX_LEN].copy_from_slice(prefix);
+    nonce[PREFIX_LEN..NONCE_LEN - 1].copy_from_slice(&counter.to_be_bytes());
+    nonce[NONCE_LEN - 1] = u8::from(last);
+    nonce
+}
+
+fn random<const N: usize>() -> [u8; N] {
+    let mut bytes = [0; N];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+/// Reads until `buf` is full or the input ends.
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(length) => filled += length,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY_ID: &str = "test";
+
+    fn config(passphrase: &str) -> EncryptionConfig {
+        // One variable per passphrase, so tests running in parallel never
+        // change a value another test reads.
+        let env = format!("ORKA_TEST_PASSPHRASE_{}", passphrase.len());
+        std::env::set_var(&env, passphrase);
+        EncryptionConfig {
+            key_id: Some(KEY_ID.to_string()),
+            keys: vec![EncryptionKey {
+                id: KEY_ID.to_string(),
+                passphrase_env: Some(env),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn encrypt(data: &[u8]) -> Vec<u8> {
+        let config = config("correct horse");
+        let mut writer = EncryptingWriter::new(Vec::new(), config.current_key().unwrap()).unwrap();
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap()
+    }
+
+    fn decrypt(file: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+        let mut plain = Vec::new();
+        DecryptingReader::new(file, &config(passphrase))?.read_to_end(&mut plain)?;
+        Ok(plain)
+    }
+
+    fn header_len() -> usize {
+        MAGIC.len() + 1 + KEY_ID.len() + SALT_LEN + NONCE_LEN + WRAPPED_KEY_LEN + 4 + PREFIX_LEN
+    }
+
+    fn data(length: usize) -> Vec<u8> {
+        (0..length).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn round_trips() {
+        for length in [0, CHUNK_SIZE, CHUNK_SIZE + 1, 3 * CHUNK_SIZE + 100] {
+            let data = data(length);
+            let file = encrypt(&data);
+            let chunks = length.div_ceil(CHUNK_SIZE).max(1);
+            assert_eq!(file.len(), header_len() + length + chunks * TAG_LEN, "length {}", length);
+            assert_eq!(decrypt(&file, "correct horse").unwrap(), data, "length {}", length);
+        }
+    }
+
+    #[test]
+    fn rejects_a_truncated_file() {
+        let file = encrypt(&data(2 * CHUNK_SIZE + 100));
+        assert!(decrypt(&file[..file.len() - 1], "correct horse").is_err());
+        // Without the last chunk, the one before it isn't sealed as the last.
+        assert!(decrypt(&file[..file.len() - 100 - TAG_LEN], "correct horse").is_err());
+    }
+
+    #[test]
+    fn rejects_a_changed_header() {
+        let file = encrypt(&data(100));
+        for position in [MAGIC.len() + 1, header_len() - 5, header_len() - 1] {
+            let mut changed = file.clone();
+            changed[position] ^= 1;
+            assert!(decrypt(&changed, "correct horse").is_err(), "byte {}", position);
+        }
+    }
+
+    #[test]
+    fn rejects_swapped_chunks() {
+        let mut file = encrypt(&data(2 * CHUNK_SIZE + 100));
+        let sealed = CHUNK_SIZE + TAG_LEN;
+        let (first, second) = file[header_len()..].split_at_mut(sealed);
+        first.swap_with_slice(&mut second[..sealed]);
+        assert!(decrypt(&file, "correct horse").is_err());
+    }
+
+    #[test]
+    fn checksums_with_the_same_key_after_decryption() {
+        let config = config("correct horse");
+        let writer = EncryptingWriter::new(Vec::new(), config.current_key().unwrap()).unwrap();
+        let checksum = writer.checksum_mac().chain_update(b"backup").finalize().into_bytes();
+        let file = writer.finish().unwrap();
+        let reader = DecryptingReader::new(&file[..], &config).unwrap();
+        assert_eq!(reader.checksum_mac().chain_update(b"backup").finalize().into_bytes(), checksum);
+        // Every file has its own data key, and so its own checksum key.
+        let other = EncryptingWriter::new(Vec::new(), config.current_key().unwrap()).unwrap();
+        assert_ne!(other.checksum_mac().chain_update(b"backup").finalize().into_bytes(), checksum);
+    }
+
+    #[test]
+    fn rejects_the_wrong_passphrase() {
+        let file = encrypt(&data(100));
+        let error = decrypt(&file, "battery staple").unwrap_err();
+        assert!(error.to_string().contains("wrong passphrase"), "{:#}", error);
+    }
+}
@@ -0,0 +1,186 @@
+use crate::backup::{self, BackupType};
+use crate::config::DatabaseConfig;
+use crate::connection::Connection;
+use crate::state;
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::sync::Mutex;
+
+/// What a backup taken outside the agent did to the agent's own chain.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChainImpact {
+    /// A differential or COPY_ONLY backup, or one of a database the agent
+    /// only copies; nothing the agent relies on changed.
+    None,
+    /// A full backup without COPY_ONLY: later differentials are based on it,
+    /// but the agent doesn't have it.
+    BreaksDifferentialBase,
+    /// A log backup without COPY_ONLY: it truncated log records the agent's
+    /// log chain needs.
+    BreaksLogChain,
+}
+
+impl std::fmt::Display for ChainImpact {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ChainImpact::None => "no impact",
+            ChainImpact::BreaksDifferentialBase => "breaks the differential base",
+            ChainImpact::BreaksLogChain => "breaks the log chain",
+        })
+    }
+}
+
+/// A backup of a managed database found in `msdb` that the agent did not take.
+#[derive(Serialize, Debug, Clone)]
+pub struct ForeignBackup {
+    pub backup_set_id: i32,
+    pub database: String,
+    pub backup_type: &'static str,
+    pub copy_only: bool,
+    /// Server local time.
+    pub started_at: String,
+    pub finished_at: String,
+    pub user_name: String,
+    pub device: String,
+    pub impact: ChainImpact,
+}
+
+/// Most recent findings, newest last, for the GUI.
+static FINDINGS: Lazy<Mutex<Vec<ForeignBackup>>> = Lazy::new(|| Mutex::new(Vec::new()));
+const MAX_FINDINGS: usize = 200;
+
+pub fn findings() -> Vec<ForeignBackup> {
+    FINDINGS.lock().unwrap().clone()
+}
+
+/// Reads backups of `databases` taken since the last run from `msdb`,
+/// and returns those not taken by the agent. Non-copy-only full and log
+/// backups mark the database as needing a new full backup.
+pub async fn reconcile(conn: &mut Connection, databases: &[DatabaseConfig]) -> Result<Vec<ForeignBackup>> {
+    let timeouts = conn.timeouts();
+    let client = conn.client().await?;
+    let Some(watermark) = state::history_watermark().await? else {
+        // On the first run there is no telling the agent's earlier backups
+        // from foreign ones, so history is only read from here on.
+        let seed = timeouts
+            .command("Backup history query", async {
+                client
+                    .query("SELECT ISNULL(MAX(backup_set_id), 0) AS backup_set_id FROM msdb.dbo.backupset", &[])
+                    .await?
+                    .into_row()
+                    .await
+            })
+            .await?
+            .and_then(|row| row.get::<i32, _>("backup_set_id"))
+            .unwrap_or_default();
+        tracing::info!("Reading backup history from backup set {} on.", seed);
+        state::set_history_watermark(seed).await?;
+        return Ok(Vec::new());
+    };
+    let rows = timeouts
+        .command("Backup history query", async {
+            client
+                .query(
+                    "SELECT b.backup_set_id, b.database_name, b.type, b.is_copy_only, \
+                        CONVERT(varchar(19), b.backup_start_date, 126) AS started_at, \
+                        CONVERT(varchar(19), b.backup_finish_date, 126) AS finished_at, \
+                        ISNULL(b.user_name, '') AS user_name, ISNULL(b.description, '') AS description, \
+                        m.physical_device_name \
+                     FROM msdb.dbo.backupset b \
+                     JOIN msdb.dbo.backupmediafamily m ON m.media_set_id = b.media_set_id AND m.family_sequence_number = 1 \
+                     WHERE b.backup_set_id > @P1 \
+                     ORDER BY b.backup_set_id",
+                    &[&watermark],
+                )
+                .await?
+                .into_first_result()
+                .await
+        })
+        .await?;
+
+    let mut last_backup_set_id = watermark;
+    let mut found = Vec::new();
+    for row in &rows {
+        let backup_set_id: i32 = row.get("backup_set_id").unwrap_or_default();
+        last_backup_set_id = last_backup_set_id.max(backup_set_id);
+
+        let name = row.get::<&str, _>("database_name").unwrap_or_default();
+        let Some(database) = databases.iter().find(|d| d.name.eq_ignore_ascii_case(name)) else {
+            continue;
+        };
+        if row.get::<&str, _>("description") == Some(backup::BACKUP_DESCRIPTION) {
+            continue;
+        }
+        let backup_type = match row.get::<&str, _>("type") {
+            Some("D") => BackupType::Full,
+            Some("I") => BackupType::Differential,
+            Some("L") => BackupType::Log,
+            _ => continue,
+        };
+        let copy_only = row.get::<bool, _>("is_copy_only").unwrap_or(false);
+        let impact = match backup_type {
+            _ if copy_only || database.copy_only() => ChainImpact::None,
+            BackupType::Full => ChainImpact::BreaksDifferentialBase,
+            BackupType::Log => ChainImpact::BreaksLogChain,
+            BackupType::Differential => ChainImpact::None,
+        };
+        let text = |name: &str| row.get::<&str, _>(name).unwrap_or_default().to_string();
+        found.push(ForeignBackup {
+            backup_set_id,
+            database: database.name.clone(),
+            backup_type: backup_type.as_str(),
+            copy_only,
+            started_at: text("started_at"),
+            finished_at: text("finished_at"),
+            user_name: text("user_name"),
+            device: text("physical_device_name"),
+            impact,
+        });
+    }
+
+    for backup in &found {
+        if backup.impact == ChainImpact::None {
+            tracing::info!(
+                "Found a {} backup of '{}' taken outside the agent by {} at {} to {}.",
+                backup.backup_type,
+                backup.database,
+                backup.user_name,
+                backup.started_at,
+                backup.device
+            );
+            continue;
+        }
+        tracing::warn!(
+            "ALERT: a {} backup of '{}' taken without COPY_ONLY by {} at {} to {} {}; the next backup will be a full backup.",
+            backup.backup_type,
+            backup.database,
+            backup.user_name,
+            backup.started_at,
+            backup.device,
+            backup.impact
+        );
+        // Such a full leaves the log chain intact, so log backups go on.
+        let log_chain_broken = backup.impact == ChainImpact::BreaksLogChain;
+        state::update_database(&backup.database, |state| {
+            if log_chain_broken {
+                state.full_required = true;
+            } else {
+                state.differential_base_lost = true;
+            }
+        })
+        .await?;
+    }
+
+    if last_backup_set_id != watermark {
+        state::set_history_watermark(last_backup_set_id).await?;
+    }
+
+    let mut findings = FINDINGS.lock().unwrap();
+    findings.extend(found.iter().cloned());
+    let excess = findings.len().saturating_sub(MAX_FINDINGS);
+    findings.drain(..excess);
+
+    Ok(found)
+}
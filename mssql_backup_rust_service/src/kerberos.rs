@@ -0,0 +1,74 @@
+use crate::config::KerberosConfig;
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// Makes sure a Kerberos ticket is available before an integrated login,
+/// getting one from the keytab when there is none.
+pub async fn ensure_ticket(kerberos: &KerberosConfig) -> Result<()> {
+    match has_ticket().await {
+        Some(true) => Ok(()),
+        _ if kerberos.keytab.is_some() => acquire(kerberos).await,
+        Some(false) => {
+            tracing::error!(
+                "No Kerberos ticket available for integrated authentication. \
+                 Run kinit as the service user, or set mssql.kerberos.principal and keytab."
+            );
+            bail!("No Kerberos ticket available")
+        }
+        // Without klist, leave it to the GSSAPI login to report.
+        None => Ok(()),
+    }
+}
+
+/// Whether the credential cache holds a valid ticket, or `None` if `klist`
+/// is not installed.
+async fn has_ticket() -> Option<bool> {
+    match Command::new("klist").arg("-s").status().await {
+        Ok(status) => Some(status.success()),
+        Err(e) => {
+            tracing::debug!("Could not run klist: {}", e);
+            None
+        }
+    }
+}
+
+/// Gets a new ticket for the configured principal from its keytab.
+pub async fn acquire(kerberos: &KerberosConfig) -> Result<()> {
+    let (Some(principal), Some(keytab)) = (&kerberos.principal, &kerberos.keytab) else {
+        bail!("mssql.kerberos: principal and keytab must both be set");
+    };
+    if !Path::new(keytab).is_file() {
+        bail!("mssql.kerberos: keytab {} not found", keytab);
+    }
+    let output = Command::new("kinit")
+        .arg("-k")
+        .arg("-t")
+        .arg(keytab)
+        .arg(principal)
+        .output()
+        .await
+        .context("Failed to run kinit; is the Kerberos client installed?")?;
+    if !output.status.success() {
+        bail!(
+            "kinit for {} failed: {}",
+            principal,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    tracing::info!("Acquired a Kerberos ticket for {} from {}", principal, keytab);
+    Ok(())
+}
+
+/// Gets a fresh ticket from the keytab at the configured interval, well
+/// before the usual ten-hour ticket lifetime runs out.
+pub async fn renew_task(kerberos: KerberosConfig) {
+    let interval = u64::from(kerberos.renew_interval_minutes.unwrap_or(60).max(1));
+    loop {
+        if let Err(e) = acquire(&kerberos).await {
+            tracing::error!("Failed to renew the Kerberos ticket: {:?}", e);
+        }
+        tokio::time::sleep(Duration::from_secs(interval * 60)).await;
+    }
+}
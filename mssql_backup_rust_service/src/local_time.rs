@@ -0,0 +1,27 @@
+//! The agent's local UTC offset, for schedules, upload windows, log file
+//! names and restore times given without an offset.
+//!
+//! On Unix the offset can only be read while the process has a single
+//! thread, so the offset at startup is kept: there a daylight saving change
+//! takes effect once the agent restarts. Elsewhere it is read on every call.
+
+use once_cell::sync::Lazy;
+use time::{OffsetDateTime, UtcOffset};
+
+static STARTUP_OFFSET: Lazy<Option<UtcOffset>> = Lazy::new(|| UtcOffset::current_local_offset().ok());
+
+/// Reads the startup offset; called from `early_init` before any thread
+/// starts. Returns whether it is known.
+pub fn init() -> bool {
+    STARTUP_OFFSET.is_some()
+}
+
+/// The current local offset, `None` if it couldn't be determined.
+pub fn offset() -> Option<UtcOffset> {
+    UtcOffset::current_local_offset().ok().or(*STARTUP_OFFSET)
+}
+
+/// The current local time, in UTC if the offset is unknown.
+pub fn now() -> OffsetDateTime {
+    OffsetDateTime::now_utc().to_offset(offset().unwrap_or(UtcOffset::UTC))
+}
@@ -1,5 +1,5 @@
 use std::path::{Path, PathBuf};
-use time::OffsetDateTime;
+use crate::local_time;
 use time::macros::format_description;
 
 pub fn get_log_filepath() -> PathBuf {
@@ -7,9 +7,7 @@ pub fn get_log_filepath() -> PathBuf {
     let log_dir = exe_path.parent().unwrap_or_else(|| Path::new("."));
 
     let format = format_description!("[year]-[month]-[day]");
-    let now = OffsetDateTime::now_utc();
-    let (h, m, s) = time::UtcOffset::current_local_offset().unwrap_or_else(|_| time::UtcOffset::UTC).as_hms();
-    let today = now.to_offset(time::UtcOffset::from_hms(h,m,s).unwrap()).format(&format).unwrap();
+    let today = local_time::now().format(&format).unwrap();
 
     log_dir.join(format!("service.log.{}", today))
 }
@@ -1,34 +1,39 @@
 // #![windows_subsystem = "windows"]
 
-use iced::{Alignment, Application, Command, Element, Length, Settings, Theme};
-use iced::widget::{button, column, container, row, scrollable, text, text_input};
+use iced::{Alignment, Application, Command, Element, Length, Settings, Subscription, Theme};
+use iced::widget::{button, checkbox, column, container, row, scrollable, text, text_input};
 
 mod config;
+mod api;
 mod backup;
+mod connection;
+#[cfg(unix)]
+mod kerberos;
 mod upload;
+mod encoding;
+mod encryption;
+mod throttle;
 mod cleanup;
+mod local_time;
 mod logging;
 mod styling;
+mod schedule;
+mod state;
+mod sql;
+mod progress;
+mod preflight;
+mod history;
+mod pitr;
+mod restore;
 
 use anyhow::Result;
 use std::path::Path;
 use std::time::Duration;
 use ctor::ctor;
 use time::{format_description::well_known::Rfc3339, OffsetDateTime};
-use tracing_subscriber::{prelude::*, EnvFilter};
+use tracing_subscriber::{filter::Targets, prelude::*, EnvFilter};
 use once_cell::sync::Lazy;
 
-#[derive(Debug, Clone, serde::Deserialize)]
-struct BackupEntry {
-    id: u64,
-    db_name: String,
-    file_path: String,
-    file_size_bytes: u64,
-    backup_started_at: String,
-    backup_completed_at: String,
-    status: String,
-}
-
 async fn load_and_parse_logs() -> Result<Vec<LogEntry>, String> {
     let log_path = logging::get_log_filepath();
     let content = tokio::fs::read_to_string(log_path)
@@ -49,8 +54,7 @@ async fn load_and_parse_logs() -> Result<Vec<LogEntry>, String> {
 
             let timestamp_utc =
                 OffsetDateTime::parse(timestamp_utc_str, &Rfc3339).map_err(|e| e.to_string())?;
-            let local_offset =
-                time::UtcOffset::current_local_offset().map_err(|e| e.to_string())?;
+            let local_offset = local_time::offset().ok_or("Unknown local time offset")?;
             let timestamp_local = timestamp_utc.to_offset(local_offset);
 
             entries.push(LogEntry {
@@ -70,8 +74,13 @@ static LOGGING_GUARD: Lazy<tracing_appender::non_blocking::WorkerGuard> = Lazy::
 
 #[ctor]
 fn early_init() {
+    // Before the logging thread starts; see local_time.
+    let offset_known = local_time::init();
     // Accessing the Lazy guard will initialize it.
     Lazy::force(&LOGGING_GUARD);
+    if !offset_known {
+        tracing::warn!("Failed to determine the local time offset; schedules and upload windows use UTC.");
+    }
     tracing::info!("Early init logging complete.");
 }
 
@@ -96,6 +105,9 @@ pub fn main() -> Result<()> {
     tracing::info!("Application starting...");
 
     let args: Vec<String> = env::args().collect();
+    if let Some(request) = parse_restore_args(&args)? {
+        return run_restore_cli(request);
+    }
     let is_service = args.iter().any(|arg| arg == "--service");
     let instance = SingleInstance::new(APP_ID)?;
 
@@ -118,6 +130,72 @@ pub fn main() -> Result<()> {
     Ok(())
 }
 
+/// `--restore <backup id> --target <database> [--data-dir <dir>] [--log-dir <dir>] [--replace]`
+/// or `--restore-database <database> --stop-at <time> [--source-server <server id>] --target <database> ...`
+fn parse_restore_args(args: &[String]) -> Result<Option<restore::RestoreRequest>> {
+    let source = if let Some(backup_id) = arg_value(args, "--restore") {
+        let backup_id = backup_id
+            .parse()
+            .map_err(|_| anyhow::anyhow!("--restore expects a backup id, got '{}'", backup_id))?;
+        restore::RestoreSource::Backup(backup_id)
+    } else if let Some(database) = arg_value(args, "--restore-database") {
+        let stop_at = arg_value(args, "--stop-at")
+            .ok_or_else(|| anyhow::anyhow!("--restore-database requires --stop-at <time>"))?;
+        let server_id = match arg_value(args, "--source-server") {
+            Some(id) => Some(
+                id.parse()
+                    .map_err(|_| anyhow::anyhow!("--source-server expects a server id, got '{}'", id))?,
+            ),
+            None => None,
+        };
+        restore::RestoreSource::PointInTime {
+            server_id,
+            database: database.to_string(),
+            stop_at: pitr::parse_stop_at(stop_at, local_time::offset())?,
+        }
+    } else {
+        return Ok(None);
+    };
+    let target_database = arg_value(args, "--target")
+        .ok_or_else(|| anyhow::anyhow!("Restoring requires --target <database>"))?;
+
+    Ok(Some(restore::RestoreRequest {
+        source,
+        target_database: target_database.to_string(),
+        data_dir: arg_value(args, "--data-dir").map(str::to_string),
+        log_dir: arg_value(args, "--log-dir").map(str::to_string),
+        replace: args.iter().any(|arg| arg == "--replace"),
+    }))
+}
+
+fn arg_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str)
+}
+
+fn run_restore_cli(request: restore::RestoreRequest) -> Result<()> {
+    let config = config::load_config("config.toml")?;
+    let rt = tokio::runtime::Runtime::new()?;
+    rt.block_on(async {
+        tokio::spawn(progress::log_events());
+        if request.replace && restore::target_exists(&config, &request.target_database).await? {
+            print!(
+                "Database '{}' exists and will be overwritten. Type its name to confirm: ",
+                request.target_database
+            );
+            std::io::stdout().flush()?;
+            let mut answer = String::new();
+            std::io::stdin().read_line(&mut answer)?;
+            if answer.trim() != request.target_database {
+                anyhow::bail!("Restore cancelled.");
+            }
+        }
+        restore::restore_backup(&config, &request).await
+    })
+}
+
 fn run_service() -> Result<()> {
     let rt = tokio::runtime::Runtime::new()?;
     rt.spawn(async {
@@ -151,11 +229,20 @@ fn run_service() -> Result<()> {
         &quit_item,
     ])?;
 
-    let _tray_icon = tray_builder
+    let tray_icon = tray_builder
         .with_menu(Box::new(tray_menu))
         .build()?;
+    let mut tooltip = String::new();
 
     loop {
+        let new_tooltip = progress::status_line().unwrap_or_else(|| "MSSQL Backup Service".to_string());
+        if new_tooltip != tooltip {
+            if let Err(e) = tray_icon.set_tooltip(Some(&new_tooltip)) {
+                tracing::warn!("Failed to update tray tooltip: {}", e);
+            }
+            tooltip = new_tooltip;
+        }
+
         if let Ok(event) = menu_channel.try_recv() {
             if event.id == show_item.id() {
                 show_gui();
@@ -207,6 +294,23 @@ enum ViewState {
     Settings,
     Logs,
     Backups,
+    Restore,
+    History,
+}
+
+#[derive(Debug, Clone, Default)]
+struct RestoreForm {
+    backup_id: u64,
+    source_server_id: Option<u64>,
+    source_database: String,
+    target_database: String,
+    data_dir: String,
+    log_dir: String,
+    /// Empty to restore just the selected backup.
+    stop_at: String,
+    replace: bool,
+    confirm: String,
+    running: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -222,8 +326,11 @@ struct App {
     view_state: ViewState,
     config: config::Config,
     original_config: Option<config::Config>,
+    databases_input: String,
+    progress: Option<String>,
     logs: Vec<LogEntry>,
-    backups: Vec<BackupEntry>,
+    backups: Vec<api::BackupEntry>,
+    restore: RestoreForm,
 }
 
 #[derive(Debug, Clone)]
@@ -238,23 +345,42 @@ enum Message {
     Config(ConfigMessage),
     Cancel,
     ViewBackups,
-    BackupsLoaded(Result<Vec<BackupEntry>, String>),
+    ViewHistory,
+    BackupsLoaded(Result<Vec<api::BackupEntry>, String>),
     DownloadBackup(u64),
     OpenUrl(String),
+    ProgressTick,
+    ShowRestore(u64),
+    Restore(RestoreMessage),
+    StartRestore,
+    RestoreFinished(Result<(), String>),
+}
+
+#[derive(Debug, Clone)]
+pub enum RestoreMessage {
+    TargetChanged(String),
+    DataDirChanged(String),
+    LogDirChanged(String),
+    StopAtChanged(String),
+    ReplaceToggled(bool),
+    ConfirmChanged(String),
 }
 
 #[derive(Debug, Clone)]
 pub enum ConfigMessage {
+    ConnectionStringChanged(String),
     HostChanged(String),
     PortChanged(String),
     UserChanged(String),
     PassChanged(String),
-    DatabaseChanged(String),
+    DatabasesChanged(String),
     InstanceNameChanged(String),
     ApiUrlChanged(String),
     ServerTokenChanged(String),
     AuthTokenChanged(String),
     TempPathChanged(String),
+    ServerBackupDirChanged(String),
+    AgentReadDirChanged(String),
 }
 
 impl Application for App {
@@ -271,9 +397,12 @@ impl Application for App {
                         status: "Backup service running...".to_string(),
                         view_state: ViewState::Main,
                         original_config: Some(config.clone()),
+                        databases_input: databases_input(&config),
+                        progress: None,
                         config,
                         logs: vec![],
                         backups: vec![],
+                        restore: RestoreForm::default(),
                     };
                     (app, Command::perform(run_app_wrapper(), Message::StatusChanged))
                 }
@@ -283,8 +412,11 @@ impl Application for App {
                         view_state: ViewState::Settings,
                         config: config::Config::default(),
                         original_config: None,
+                        databases_input: String::new(),
+                        progress: None,
                         logs: vec![],
                         backups: vec![],
+                        restore: RestoreForm::default(),
                     };
                     (app, Command::none())
                 }
@@ -295,8 +427,11 @@ impl Application for App {
                 view_state: ViewState::Settings,
                 config: config::Config::default(),
                 original_config: None,
+                databases_input: String::new(),
+                progress: None,
                 logs: vec![],
                 backups: vec![],
+                restore: RestoreForm::default(),
             };
             (app, Command::none())
         }
@@ -310,8 +445,12 @@ impl Application for App {
         match message {
             Message::Setup => {
                 self.original_config = Some(self.config.clone());
+                self.databases_input = databases_input(&self.config);
                 self.view_state = ViewState::Settings;
             }
+            Message::ViewHistory => {
+                self.view_state = ViewState::History;
+            }
             Message::ViewLogs => {
                 return Command::perform(load_and_parse_logs(), Message::LogsLoaded);
             }
@@ -346,11 +485,17 @@ impl Application for App {
             }
             Message::Config(config_message) => {
                 match config_message {
+                    ConfigMessage::ConnectionStringChanged(s) => {
+                        self.config.mssql.connection_string = Some(s).filter(|s| !s.is_empty())
+                    }
                     ConfigMessage::HostChanged(s) => self.config.mssql.host = Some(s),
                     ConfigMessage::PortChanged(s) => self.config.mssql.port = s.parse().ok(),
                     ConfigMessage::UserChanged(s) => self.config.mssql.user = Some(s),
                     ConfigMessage::PassChanged(s) => self.config.mssql.pass = Some(s),
-                    ConfigMessage::DatabaseChanged(s) => self.config.mssql.database = s,
+                    ConfigMessage::DatabasesChanged(s) => {
+                        set_database_names(&mut self.config, &s);
+                        self.databases_input = s;
+                    }
                     ConfigMessage::InstanceNameChanged(s) => {
                         self.config.mssql.instance_name = Some(s)
                     }
@@ -358,19 +503,26 @@ impl Application for App {
                     ConfigMessage::ServerTokenChanged(s) => self.config.api.server_token = s,
                     ConfigMessage::AuthTokenChanged(s) => self.config.api.auth_token = s,
                     ConfigMessage::TempPathChanged(s) => self.config.backup.temp_path = s,
+                    ConfigMessage::ServerBackupDirChanged(s) => {
+                        self.config.backup.server_backup_dir = Some(s).filter(|s| !s.is_empty())
+                    }
+                    ConfigMessage::AgentReadDirChanged(s) => {
+                        self.config.backup.agent_read_dir = Some(s).filter(|s| !s.is_empty())
+                    }
                 }
             }
             Message::Cancel => {
                 if let Some(original_config) = self.original_config.take() {
                     self.config = original_config;
                 }
+                self.databases_input = databases_input(&self.config);
                 self.view_state = ViewState::Main;
                 self.status = "Editing cancelled.".to_string();
             }
             Message::ViewBackups => {
                 self.view_state = ViewState::Backups;
                 let config = self.config.clone();
-                return Command::perform(fetch_backups(config), Message::BackupsLoaded);
+                return Command::perform(api::fetch_backups(config), Message::BackupsLoaded);
             }
             Message::BackupsLoaded(Ok(backups)) => {
                 self.backups = backups;
@@ -380,8 +532,32 @@ impl Application for App {
             }
             Message::DownloadBackup(backup_id) => {
                 let config = self.config.clone();
+                // The browser would save a compressed or encrypted backup as it is stored.
+                if let Some(entry) = self
+                    .backups
+                    .iter()
+                    .find(|b| b.id == backup_id && b.is_encoded())
+                    .cloned()
+                {
+                    self.status = format!("Downloading backup {}...", backup_id);
+                    return Command::perform(
+                        async move {
+                            std::fs::create_dir_all(&config.backup.temp_path)?;
+                            let dest = Path::new(&config.backup.temp_path).join(format!(
+                                "download_{}_{}.bak",
+                                backup::file_name_safe(&entry.db_name),
+                                entry.id
+                            ));
+                            restore::download_backup(&config, &entry, &dest).await.map(|_| dest)
+                        },
+                        |result| match result {
+                            Ok(dest) => Message::StatusChanged(format!("Backup saved to {}", dest.display())),
+                            Err(e) => Message::StatusChanged(format!("Error: {:#}", e)),
+                        },
+                    );
+                }
                 return Command::perform(
-                    request_download_link(config, backup_id),
+                    api::request_download_link(config, backup_id),
                     |result| match result {
                         Ok(url) => Message::OpenUrl(url),
                         Err(e) => Message::StatusChanged(format!("Error: {}", e)),
@@ -393,17 +569,92 @@ impl Application for App {
                     self.status = "Failed to open web browser".to_string();
                 }
             }
+            Message::ProgressTick => {
+                self.progress = progress::status_line();
+            }
+            Message::ShowRestore(backup_id) => {
+                if let Some(entry) = self.backups.iter().find(|b| b.id == backup_id) {
+                    self.restore = RestoreForm {
+                        backup_id,
+                        source_server_id: entry.server_id,
+                        source_database: entry.db_name.clone(),
+                        target_database: entry.db_name.clone(),
+                        ..Default::default()
+                    };
+                    self.view_state = ViewState::Restore;
+                }
+            }
+            Message::Restore(restore_message) => match restore_message {
+                RestoreMessage::TargetChanged(s) => self.restore.target_database = s,
+                RestoreMessage::DataDirChanged(s) => self.restore.data_dir = s,
+                RestoreMessage::LogDirChanged(s) => self.restore.log_dir = s,
+                RestoreMessage::StopAtChanged(s) => self.restore.stop_at = s,
+                RestoreMessage::ReplaceToggled(replace) => {
+                    self.restore.replace = replace;
+                    self.restore.confirm.clear();
+                }
+                RestoreMessage::ConfirmChanged(s) => self.restore.confirm = s,
+            },
+            Message::StartRestore => {
+                let form = &self.restore;
+                let source = if form.stop_at.trim().is_empty() {
+                    restore::RestoreSource::Backup(form.backup_id)
+                } else {
+                    match pitr::parse_stop_at(&form.stop_at, local_time::offset()) {
+                        Ok(stop_at) => restore::RestoreSource::PointInTime {
+                            server_id: form.source_server_id,
+                            database: form.source_database.clone(),
+                            stop_at,
+                        },
+                        Err(e) => {
+                            self.status = e.to_string();
+                            return Command::none();
+                        }
+                    }
+                };
+                let request = restore::RestoreRequest {
+                    source,
+                    target_database: form.target_database.trim().to_string(),
+                    data_dir: Some(form.data_dir.trim().to_string()).filter(|s| !s.is_empty()),
+                    log_dir: Some(form.log_dir.trim().to_string()).filter(|s| !s.is_empty()),
+                    replace: form.replace,
+                };
+                self.restore.running = true;
+                self.status = format!("Restoring into '{}'...", request.target_database);
+                let config = self.config.clone();
+                return Command::perform(
+                    async move {
+                        restore::restore_backup(&config, &request)
+                            .await
+                            .map_err(|e| format!("{:#}", e))
+                    },
+                    Message::RestoreFinished,
+                );
+            }
+            Message::RestoreFinished(result) => {
+                self.restore.running = false;
+                self.status = match result {
+                    Ok(()) => format!("Database '{}' restored successfully.", self.restore.target_database),
+                    Err(e) => format!("Restore failed: {}", e),
+                };
+            }
         }
         Command::none()
     }
 
+    fn subscription(&self) -> Subscription<Message> {
+        iced::time::every(Duration::from_secs(1)).map(|_| Message::ProgressTick)
+    }
+
     fn view(&self) -> Element<'_, Message> {
         match self.view_state {
             ViewState::Main => column![
                 text(&self.status),
+                text(self.progress.as_deref().unwrap_or("")),
                 button("Setup").on_press(Message::Setup),
                 button("View Logs").on_press(Message::ViewLogs),
                 button("View Backups").on_press(Message::ViewBackups),
+                button("Foreign Backups").on_press(Message::ViewHistory),
                 button("Quit").on_press(Message::Quit),
             ]
             .padding(20)
@@ -468,6 +719,11 @@ impl Application for App {
                             .width(Length::FillPortion(2))
                             .center_x(),
                     )
+                    .push(
+                        container(text("Restore"))
+                            .width(Length::FillPortion(2))
+                            .center_x(),
+                    )
                     .spacing(10)
                     .align_items(Alignment::Center);
 
@@ -500,6 +756,14 @@ impl Application for App {
                                         .width(Length::FillPortion(2))
                                         .center_x(),
                                     )
+                                    .push(
+                                        container(
+                                            button("Restore")
+                                                .on_press(Message::ShowRestore(entry.id)),
+                                        )
+                                        .width(Length::FillPortion(2))
+                                        .center_x(),
+                                    )
                                     .spacing(10)
                                     .align_items(Alignment::Center),
                             )
@@ -523,9 +787,132 @@ impl Application for App {
                     .spacing(10)
                     .into()
             }
+            ViewState::History => {
+                let header = row![]
+                    .push(text("Started").width(Length::Fixed(180.0)))
+                    .push(text("Database").width(Length::FillPortion(2)))
+                    .push(text("Type").width(Length::Fixed(120.0)))
+                    .push(text("User").width(Length::FillPortion(2)))
+                    .push(text("Device").width(Length::FillPortion(4)))
+                    .push(text("Impact").width(Length::FillPortion(2)))
+                    .spacing(10);
+
+                let history_rows = history::findings().into_iter().rev().enumerate().fold(
+                    column![].spacing(5),
+                    |col, (i, backup)| {
+                        let style = if i % 2 == 0 {
+                            iced::theme::Container::Custom(Box::new(styling::ContainerTheme::Even))
+                        } else {
+                            iced::theme::Container::Custom(Box::new(styling::ContainerTheme::Odd))
+                        };
+                        let backup_type = if backup.copy_only {
+                            format!("{} (copy-only)", backup.backup_type)
+                        } else {
+                            backup.backup_type.to_string()
+                        };
+
+                        col.push(
+                            container(
+                                row![]
+                                    .push(text(&backup.started_at).width(Length::Fixed(180.0)))
+                                    .push(text(&backup.database).width(Length::FillPortion(2)))
+                                    .push(text(backup_type).width(Length::Fixed(120.0)))
+                                    .push(text(&backup.user_name).width(Length::FillPortion(2)))
+                                    .push(text(&backup.device).width(Length::FillPortion(4)))
+                                    .push(text(backup.impact.to_string()).width(Length::FillPortion(2)))
+                                    .spacing(10),
+                            )
+                            .style(style),
+                        )
+                    },
+                );
+
+                let title_row = row![
+                    text("Backups Taken Outside the Agent").size(24),
+                    row![]
+                        .width(Length::Fill)
+                        .align_items(Alignment::End)
+                        .spacing(10)
+                        .push(button("Back").on_press(Message::BackToMain))
+                ]
+                .align_items(Alignment::Center)
+                .spacing(20);
+
+                column![title_row, header, scrollable(history_rows)]
+                    .padding(20)
+                    .spacing(10)
+                    .into()
+            }
+            ViewState::Restore => {
+                let form = &self.restore;
+                let confirmed = !form.replace || form.confirm.trim() == form.target_database.trim();
+                let can_start = !form.running && !form.target_database.trim().is_empty() && confirmed;
+
+                let mut content = column![
+                    text("Restore Backup").size(24),
+                    text(&self.status),
+                    text(format!("Backup {} of '{}'", form.backup_id, form.source_database)),
+                    row![
+                        text("Target Database:").width(Length::Fixed(150.0)),
+                        text_input("", &form.target_database)
+                            .on_input(|s| Message::Restore(RestoreMessage::TargetChanged(s)))
+                    ]
+                    .spacing(5),
+                    row![
+                        text("Data Directory:").width(Length::Fixed(150.0)),
+                        text_input("Instance default", &form.data_dir)
+                            .on_input(|s| Message::Restore(RestoreMessage::DataDirChanged(s)))
+                    ]
+                    .spacing(5),
+                    row![
+                        text("Log Directory:").width(Length::Fixed(150.0)),
+                        text_input("Instance default", &form.log_dir)
+                            .on_input(|s| Message::Restore(RestoreMessage::LogDirChanged(s)))
+                    ]
+                    .spacing(5),
+                    row![
+                        text("Point in Time:").width(Length::Fixed(150.0)),
+                        text_input("YYYY-MM-DD HH:MM (empty: this backup only)", &form.stop_at)
+                            .on_input(|s| Message::Restore(RestoreMessage::StopAtChanged(s)))
+                    ]
+                    .spacing(5),
+                    checkbox("Overwrite the target database if it exists (REPLACE)", form.replace)
+                        .on_toggle(|b| Message::Restore(RestoreMessage::ReplaceToggled(b))),
+                ]
+                .spacing(10);
+
+                if form.replace {
+                    content = content.push(
+                        row![
+                            text("Type the target database name to confirm:").width(Length::Fixed(300.0)),
+                            text_input("", &form.confirm)
+                                .on_input(|s| Message::Restore(RestoreMessage::ConfirmChanged(s)))
+                        ]
+                        .spacing(5),
+                    );
+                }
+
+                let buttons = row![
+                    button("Back").on_press(Message::ViewBackups),
+                    button("Restore").on_press_maybe(can_start.then_some(Message::StartRestore)),
+                ]
+                .spacing(10);
+
+                content.push(buttons).padding(20).spacing(10).into()
+            }
             ViewState::Settings => {
                 let mut content = column![
                     text("Settings").size(24),
+                    row![
+                        text("Connection String:").width(Length::Fixed(120.0)),
+                        text_input(
+                            "Server=tcp:host,1433;... (optional)",
+                            self.config.mssql.connection_string.as_deref().unwrap_or("")
+                        )
+                        .on_input(|s| Message::Config(ConfigMessage::ConnectionStringChanged(s)))
+                    ]
+                    .spacing(5),
+                    text("Host, port, user, password and instance override the connection string when set.").size(12),
                     row![
                         text("Host:").width(Length::Fixed(120.0)),
                         text_input("", self.config.mssql.host.as_deref().unwrap_or(""))
@@ -554,9 +941,9 @@ impl Application for App {
                     ]
                     .spacing(5),
                     row![
-                        text("Database:").width(Length::Fixed(120.0)),
-                        text_input("", &self.config.mssql.database)
-                            .on_input(|s| Message::Config(ConfigMessage::DatabaseChanged(s)))
+                        text("Databases:").width(Length::Fixed(120.0)),
+                        text_input("Comma-separated database names", &self.databases_input)
+                            .on_input(|s| Message::Config(ConfigMessage::DatabasesChanged(s)))
                     ]
                     .spacing(5),
                     row![
@@ -592,6 +979,24 @@ impl Application for App {
                             .on_input(|s| Message::Config(ConfigMessage::TempPathChanged(s)))
                     ]
                     .spacing(5),
+                    row![
+                        text("Server Dir:").width(Length::Fixed(120.0)),
+                        text_input(
+                            "Backup directory as SQL Server sees it (remote servers only)",
+                            self.config.backup.server_backup_dir.as_deref().unwrap_or("")
+                        )
+                        .on_input(|s| Message::Config(ConfigMessage::ServerBackupDirChanged(s)))
+                    ]
+                    .spacing(5),
+                    row![
+                        text("Agent Dir:").width(Length::Fixed(120.0)),
+                        text_input(
+                            "The same directory as this machine sees it (default: Temp Path)",
+                            self.config.backup.agent_read_dir.as_deref().unwrap_or("")
+                        )
+                        .on_input(|s| Message::Config(ConfigMessage::AgentReadDirChanged(s)))
+                    ]
+                    .spacing(5),
                 ]
                 .spacing(10);
 
@@ -609,6 +1014,35 @@ impl Application for App {
     }
 }
 
+fn databases_input(config: &config::Config) -> String {
+    config
+        .database_entries()
+        .iter()
+        .map(|d| d.name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Replaces the configured database list with `input`, keeping the
+/// per-database overrides of entries whose names are still present.
+fn set_database_names(config: &mut config::Config, input: &str) {
+    let existing = config.database_entries();
+    config.mssql.database = None;
+    config.databases = input
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| match existing.iter().find(|d| d.name.eq_ignore_ascii_case(name)) {
+            // Keeps the overrides when only the name's case changed.
+            Some(database) => config::DatabaseConfig {
+                name: name.to_string(),
+                ..database.clone()
+            },
+            None => config::DatabaseConfig::new(name),
+        })
+        .collect();
+}
+
 async fn run_app_wrapper() -> String {
     if let Err(e) = run_app().await {
         let msg = format!("Backup thread failed: {:?}", e);
@@ -620,10 +1054,30 @@ async fn run_app_wrapper() -> String {
 
 pub async fn run_app() -> Result<()> {
     let config = config::load_config("config.toml")?;
-    let cleanup_config_path = config.backup.temp_path.clone();
+    tokio::spawn(progress::log_events());
+    let cleanup_temp_paths = config.temp_paths();
     tokio::spawn(async move {
-        cleanup::cleanup_task(cleanup_config_path).await;
+        cleanup::cleanup_task(cleanup_temp_paths).await;
     });
+    #[cfg(unix)]
+    if config.mssql.kerberos.keytab.is_some() {
+        let kerberos_config = config.mssql.kerberos.clone();
+        tokio::spawn(async move {
+            kerberos::renew_task(kerberos_config).await;
+        });
+    }
+    if config.history.enabled {
+        let history_config = config.clone();
+        tokio::spawn(async move {
+            history_task(history_config).await;
+        });
+    }
+    if let Some(interval) = shortest_log_interval(&config) {
+        let log_config = config.clone();
+        tokio::spawn(async move {
+            log_backup_task(log_config, interval).await;
+        });
+    }
     loop {
         tracing::info!("Starting backup cycle...");
         match run_backup_cycle(&config).await {
@@ -636,35 +1090,428 @@ pub async fn run_app() -> Result<()> {
 }
 
 pub async fn run_backup_cycle(config: &config::Config) -> Result<()> {
+    resume_pending_uploads(config).await;
+    let mut conn = connection::Connection::new(config);
+    let databases = resolve_databases(&mut conn, config).await;
+    if databases.is_empty() {
+        anyhow::bail!("No databases configured for backup.");
+    }
+
+    let mut failed = Vec::new();
+    for database in &databases {
+        match backup_database(&mut conn, config, database).await {
+            Ok(_) => tracing::info!("Backup of database '{}' completed successfully.", database.name),
+            Err(e) => {
+                tracing::error!("Backup of database '{}' failed: {:?}", database.name, e);
+                failed.push(database.name.clone());
+            }
+        }
+    }
+
+    if !failed.is_empty() {
+        anyhow::bail!(
+            "{} of {} database backups failed: {}",
+            failed.len(),
+            databases.len(),
+            failed.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// The configured databases plus, when discovery is enabled, every discovered
+/// database not already listed. Explicit entries keep their overrides.
+async fn resolve_databases(conn: &mut connection::Connection, config: &config::Config) -> Vec<config::DatabaseConfig> {
+    let mut databases = config.database_entries();
+    if config.discovery.enabled {
+        match backup::discover_databases(conn, config).await {
+            Ok(discovered) => {
+                for database in discovered {
+                    if !databases.iter().any(|d| d.name.eq_ignore_ascii_case(&database.name)) {
+                        databases.push(database);
+                    }
+                }
+            }
+            Err(e) => tracing::error!("Database discovery failed: {:?}", e),
+        }
+    }
+    databases
+}
+
+async fn backup_database(
+    conn: &mut connection::Connection,
+    config: &config::Config,
+    database: &config::DatabaseConfig,
+) -> Result<()> {
+    let on_secondary = match backup_replica(conn, database).await {
+        BackupReplica::Skip => return Ok(()),
+        replica => replica == BackupReplica::Secondary,
+    };
+    let db_state = state::database_state(&database.name).await?;
+    let backup_type = if db_state.full_required {
+        tracing::info!("A new full backup of '{}' is required to restart its log chain.", database.name);
+        backup::BackupType::Full
+    } else if db_state.differential_base_lost {
+        tracing::info!(
+            "A new full backup of '{}' is required: a full backup taken elsewhere replaced the differential base.",
+            database.name
+        );
+        backup::BackupType::Full
+    } else {
+        schedule::choose_backup_type(
+            database.schedule(&config.backup),
+            db_state.last_full.as_ref().map(|full| full.started_at),
+            local_time::now(),
+        )
+    };
+    // A copy-only full never becomes a differential base, so copy-only
+    // databases and secondary replicas always get full backups.
+    let full_instead = match backup_type {
+        backup::BackupType::Differential if database.copy_only() => Some("the database is copy-only"),
+        backup::BackupType::Differential if on_secondary => Some("this is a secondary replica"),
+        backup::BackupType::Differential if db_state.last_full.as_ref().is_some_and(|full| full.copy_only) => {
+            Some("its last full backup was copy-only")
+        }
+        _ => None,
+    };
+    let backup_type = if let Some(reason) = full_instead {
+        tracing::info!("Database '{}': {}; taking a full backup instead of a differential.", database.name, reason);
+        backup::BackupType::Full
+    } else {
+        backup_type
+    };
+    // Secondary replicas only allow COPY_ONLY full backups.
+    let copy_only = database.copy_only() || (on_secondary && backup_type == backup::BackupType::Full);
+    let base_backup_id = match backup_type {
+        backup::BackupType::Differential => db_state.last_full.as_ref().and_then(|full| full.backup_id),
+        _ => None,
+    };
+
+    take_backup(conn, config, database, backup_type, copy_only, base_backup_id).await?;
+    Ok(())
+}
+
+/// Stores a full or log backup as the one the next backups of `database`
+/// build on. This happens when the backup is taken, whether or not its
+/// upload succeeds, since the server's chain already continues from it.
+async fn record_backup(database: &str, backup_type: backup::BackupType, record: state::BackupRecord) -> Result<()> {
+    match backup_type {
+        backup::BackupType::Full => {
+            state::update_database(database, |db| {
+                db.last_full = Some(record);
+                db.full_required = false;
+                db.differential_base_lost = false;
+            })
+            .await
+        }
+        backup::BackupType::Log => state::update_database(database, |db| db.last_log = Some(record)).await,
+        backup::BackupType::Differential => Ok(()),
+    }
+}
+
+/// Takes a transaction log backup of `database` and checks that it continues
+/// the recorded log chain.
+async fn log_backup_database(
+    conn: &mut connection::Connection,
+    config: &config::Config,
+    database: &config::DatabaseConfig,
+) -> Result<()> {
+    if backup_replica(conn, database).await == BackupReplica::Skip {
+        return Ok(());
+    }
+    let recovery_model = backup::recovery_model(conn, database).await?;
+    let db_state = state::database_state(&database.name).await?;
+
+    if recovery_model == "SIMPLE" {
+        if db_state.last_log.is_some() {
+            tracing::error!(
+                "ALERT: log chain of '{}' is broken: the recovery model was switched to SIMPLE.",
+                database.name
+            );
+            state::update_database(&database.name, |db| {
+                db.last_log = None;
+                db.full_required = true;
+            })
+            .await?;
+        }
+        return Ok(());
+    }
+    let Some(last_full) = db_state.last_full.as_ref() else {
+        tracing::info!("Skipping log backup of '{}' until its first full backup has been taken.", database.name);
+        return Ok(());
+    };
+    if db_state.full_required {
+        tracing::info!("Skipping log backup of '{}' until a new full backup has been taken.", database.name);
+        return Ok(());
+    }
+
+    tracing::info!("Starting log backup of database '{}'...", database.name);
+    let record = match take_backup(conn, config, database, backup::BackupType::Log, database.copy_only(), None).await {
+        Ok(record) => record,
+        Err(e) if backup::is_log_chain_missing(&e) => {
+            tracing::error!(
+                "ALERT: log chain of '{}' is broken, a new full backup is required: {}",
+                database.name,
+                e
+            );
+            state::update_database(&database.name, |db| {
+                db.last_log = None;
+                db.full_required = true;
+            })
+            .await?;
+            return Err(e);
+        }
+        Err(e) => return Err(e),
+    };
+
+    // Copy-only log backups don't truncate the log, so they never form a
+    // chain of their own; the other tool's log backups do.
+    if let Some(lsns) = record.lsns.as_ref().filter(|_| !database.copy_only()) {
+        let previous = db_state.last_log.as_ref().and_then(|log| log.lsns.as_ref());
+        if let Some(previous) = previous {
+            if previous.last_lsn != lsns.first_lsn {
+                tracing::error!(
+                    "ALERT: log chain of '{}' is broken: log backup starts at LSN {} but the previous one ended at {}. \
+                     Another tool may have taken a log backup.",
+                    database.name,
+                    lsns.first_lsn,
+                    previous.last_lsn
+                );
+            }
+        }
+        let full_checkpoint = last_full
+            .lsns
+            .as_ref()
+            .filter(|_| !last_full.copy_only)
+            .map(|full| full.checkpoint_lsn.as_str());
+        if full_checkpoint.is_some_and(|checkpoint| checkpoint != lsns.database_backup_lsn) {
+            tracing::warn!(
+                "The differential base of '{}' is no longer the agent's last full backup; \
+                 another full backup was taken outside the agent.",
+                database.name
+            );
+            state::update_database(&database.name, |db| db.differential_base_lost = true).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Backs up, verifies and uploads one backup of `database`, then deletes the
+/// local file. The backup is recorded in the agent state before the upload.
+async fn take_backup(
+    conn: &mut connection::Connection,
+    config: &config::Config,
+    database: &config::DatabaseConfig,
+    backup_type: backup::BackupType,
+    copy_only: bool,
+    base_backup_id: Option<u64>,
+) -> Result<state::BackupRecord> {
     let start_time = OffsetDateTime::now_utc();
-    let backup_filepath = match backup::perform_backup(config).await {
-        Ok(path) => {
-            tracing::info!("Backup created at: {:?}", path);
-            path
+    let backup_options = database.backup_options(&config.backup);
+    let location = match backup::perform_backup(conn, config, database, backup_type, copy_only, &backup_options).await {
+        Ok(location) => {
+            tracing::info!("Backup created at: {}", location.server);
+            location
         },
-        Err(e) => anyhow::bail!("Failed to perform backup: {}", e),
+        Err(e) => return Err(e.context("Failed to perform backup")),
     };
-    if let Err(e) = backup::verify_backup(config, &backup_filepath).await {
-        std::fs::remove_file(&backup_filepath)?;
+    let checksum = backup_options.checksum.unwrap_or(false);
+    if let Err(e) = backup::verify_backup(conn, &location, checksum).await {
+        std::fs::remove_file(&location.agent)?;
         anyhow::bail!("Failed to verify backup: {}", e);
     }
+    let restore_test = match database.verification(&config.backup) {
+        config::Verification::RestoreTest if backup_type == backup::BackupType::Full => {
+            Some(restore::test_restore(conn, &database.name, &location).await)
+        }
+        config::Verification::RestoreTest => {
+            tracing::info!("Restore tests only run for full backups; skipping for this {} backup.", backup_type);
+            None
+        }
+        config::Verification::Media => None,
+    };
+    let header = match backup::read_backup_header(conn, &location).await {
+        Ok(header) => {
+            tracing::info!(
+                "Backup header: SQL Server {}, database version {}, compatibility level {}, {} file(s)",
+                header.software_version,
+                header.database_version,
+                header.compatibility_level,
+                header.files.len()
+            );
+            Some(header)
+        }
+        Err(e) => {
+            tracing::warn!("Failed to read the backup header: {:?}", e);
+            None
+        }
+    };
+    let lsns = match backup::read_backup_lsns(conn, database, &location).await {
+        Ok(lsns) => Some(lsns),
+        Err(e) => {
+            tracing::warn!("Failed to read backup LSNs from msdb: {:?}", e);
+            header.as_ref().map(|header| header.lsns.clone())
+        }
+    };
+    if let Some(lsns) = &lsns {
+        tracing::info!("Backup LSNs: first {}, last {}", lsns.first_lsn, lsns.last_lsn);
+    }
     let end_time = OffsetDateTime::now_utc();
     let duration_seconds = (end_time - start_time).as_seconds_f64() as i64;
     let meta = upload::BackupMeta {
+        database_name: database.name.clone(),
+        backup_type,
+        copy_only,
+        base_backup_id,
+        lsns,
+        header,
+        restore_test,
         start_time,
         end_time,
         duration_seconds,
-        filepath: backup_filepath.clone(),
+        filepath: location.agent.clone(),
     };
-    if let Err(e) = upload::upload_backup(config, meta).await {
-        anyhow::bail!("Failed to upload backup: {}", e);
+    let record = meta.record();
+    record_backup(&database.name, backup_type, record.clone()).await?;
+    let backup_id = match upload::upload_backup(config, &meta).await {
+        Ok(backup_id) => backup_id,
+        Err(e) => anyhow::bail!("Failed to upload backup: {:#}", e),
+    };
+    state::set_backup_id(&database.name, &record.file_name, backup_id).await?;
+    Ok(state::BackupRecord { backup_id, ..record })
+}
+
+/// Finishes uploads that were interrupted, by a restart or by running out of
+/// retries. The backups were recorded when they were taken; only their API
+/// id is filled in.
+async fn resume_pending_uploads(config: &config::Config) {
+    let pending_uploads = match state::pending_uploads().await {
+        Ok(pending_uploads) => pending_uploads,
+        Err(e) => {
+            tracing::error!("Failed to resume interrupted uploads: {:?}", e);
+            return;
+        }
+    };
+    for pending in pending_uploads {
+        if !pending.path.is_file() {
+            tracing::warn!("Dropping the interrupted upload of {:?}: the file no longer exists.", pending.path);
+            if let Err(e) = state::remove_pending_upload(&pending.upload_id).await {
+                tracing::error!("Failed to update the agent state: {:?}", e);
+            }
+            continue;
+        }
+        tracing::info!("Resuming the interrupted upload of {:?}...", pending.path);
+        let backup_id = match upload::resume_upload(config, &pending).await {
+            Ok(backup_id) => backup_id,
+            Err(e) => {
+                tracing::error!("Failed to resume the upload of {:?}: {:?}", pending.path, e);
+                continue;
+            }
+        };
+        if let Err(e) = state::set_backup_id(&pending.database, &pending.record.file_name, backup_id).await {
+            tracing::error!("Failed to record the backup of '{}': {:?}", pending.database, e);
+        }
     }
-    if let Err(e) = std::fs::remove_file(&backup_filepath) {
-        tracing::error!("Failed to delete local backup file {:?}: {}", backup_filepath, e);
-    } else {
-        tracing::info!("Local backup file {:?} deleted.", backup_filepath);
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum BackupReplica {
+    /// A primary replica, or a database outside any availability group.
+    Primary,
+    Secondary,
+    /// Not the preferred backup replica of its availability group.
+    Skip,
+}
+
+/// Decides whether this replica backs up `database`, following the backup
+/// preference of its availability group.
+async fn backup_replica(conn: &mut connection::Connection, database: &config::DatabaseConfig) -> BackupReplica {
+    match backup::replica_role(conn, database).await {
+        Ok(Some(role)) if !role.is_preferred => {
+            tracing::info!(
+                "Skipping '{}': this replica is not the preferred backup replica of availability group '{}' \
+                 (backup preference: {}).",
+                database.name,
+                role.group,
+                role.preference
+            );
+            BackupReplica::Skip
+        }
+        Ok(Some(role)) if !role.is_primary => BackupReplica::Secondary,
+        Ok(_) => BackupReplica::Primary,
+        Err(e) => {
+            tracing::warn!(
+                "Could not read the availability group role of '{}', backing up as a standalone database: {:?}",
+                database.name,
+                e
+            );
+            BackupReplica::Primary
+        }
+    }
+}
+
+/// Checks the `msdb` backup history for backups taken outside the agent.
+async fn history_task(config: config::Config) {
+    let interval = u64::from(config.history.interval_minutes.unwrap_or(60).max(1));
+    let mut conn = connection::Connection::new(&config);
+    loop {
+        let databases = resolve_databases(&mut conn, &config).await;
+        match history::reconcile(&mut conn, &databases).await {
+            Ok(found) if !found.is_empty() && config.history.report_to_api => {
+                if let Err(e) = api::report_foreign_backups(&config, &found).await {
+                    tracing::error!("Failed to report foreign backups to the API: {:?}", e);
+                }
+            }
+            Ok(_) => {}
+            Err(e) => tracing::error!("Failed to read the msdb backup history: {:?}", e),
+        }
+        tokio::time::sleep(Duration::from_secs(interval * 60)).await;
+    }
+}
+
+fn shortest_log_interval(config: &config::Config) -> Option<u32> {
+    config
+        .databases
+        .iter()
+        .filter_map(|d| d.schedule.as_ref())
+        .chain([&config.backup.schedule])
+        .filter_map(|schedule| schedule.log_interval_minutes)
+        .min()
+}
+
+/// Runs log backups for every database with `log_interval_minutes` set,
+/// each once its interval has elapsed since its last log backup.
+async fn log_backup_task(config: config::Config, tick_minutes: u32) {
+    loop {
+        tokio::time::sleep(Duration::from_secs(u64::from(tick_minutes.max(1)) * 60)).await;
+
+        let mut conn = connection::Connection::new(&config);
+        let databases = resolve_databases(&mut conn, &config).await;
+        for database in &databases {
+            let Some(interval) = database.schedule(&config.backup).log_interval_minutes else {
+                continue;
+            };
+            let db_state = match state::database_state(&database.name).await {
+                Ok(db_state) => db_state,
+                Err(e) => {
+                    tracing::error!("Log backup of database '{}' skipped: {:?}", database.name, e);
+                    continue;
+                }
+            };
+            let due = db_state.last_log.as_ref().is_none_or(|log| {
+                OffsetDateTime::now_utc() - log.started_at
+                    >= time::Duration::minutes(i64::from(interval)) - time::Duration::seconds(30)
+            });
+            if !due {
+                continue;
+            }
+
+            if let Err(e) = log_backup_database(&mut conn, &config, database).await {
+                tracing::error!("Log backup of database '{}' failed: {:?}", database.name, e);
+            }
+        }
     }
-    Ok(())
 }
 
 fn init_logging() -> tracing_appender::non_blocking::WorkerGuard {
@@ -683,67 +1530,16 @@ fn init_logging() -> tracing_appender::non_blocking::WorkerGuard {
         .with_writer(non_blocking_file)
         .with_ansi(false);
 
-    let filter = EnvFilter::new("mssql_backup_rust_service=info,iced=off");
+    let filter = || EnvFilter::new("mssql_backup_rust_service=info,iced=off");
+    // tiberius reports the server's informational messages (BACKUP STATS) as
+    // INFO events; only the progress layer sees them.
+    let progress_filter = Targets::new().with_target("tiberius", tracing::Level::INFO);
 
     tracing_subscriber::registry()
-        .with(filter)
-        .with(console_layer)
-        .with(file_layer)
+        .with(console_layer.with_filter(filter()))
+        .with(file_layer.with_filter(filter()))
+        .with(progress::ProgressLayer.with_filter(progress_filter))
         .init();
 
     guard
 }
-
-async fn fetch_backups(config: config::Config) -> Result<Vec<BackupEntry>, String> {
-    let client = reqwest::Client::new();
-    let url = format!("{}/api/backups", config.api.url.trim_end_matches('/'));
-    let response = client
-        .get(&url)
-        .bearer_auth(&config.api.auth_token)
-        .send()
-        .await
-        .map_err(|e| e.to_string())?;
-
-    if response.status().is_success() {
-        let backups = response
-            .json::<Vec<BackupEntry>>()
-            .await
-            .map_err(|e| e.to_string())?;
-        Ok(backups)
-    } else {
-        Err(format!("Failed to fetch backups: {}", response.status()))
-    }
-}
-
-#[derive(serde::Deserialize)]
-struct DownloadUrl {
-    url: String,
-}
-
-async fn request_download_link(config: config::Config, backup_id: u64) -> Result<String, String> {
-    let client = reqwest::Client::new();
-    let url = format!(
-        "{}/api/backups/{}/download",
-        config.api.url.trim_end_matches('/'),
-        backup_id
-    );
-    let response = client
-        .get(&url)
-        .bearer_auth(&config.api.auth_token)
-        .send()
-        .await
-        .map_err(|e| e.to_string())?;
-
-    if response.status().is_success() {
-        let download_url = response
-            .json::<DownloadUrl>()
-            .await
-            .map_err(|e| e.to_string())?;
-        Ok(download_url.url)
-    } else {
-        Err(format!(
-            "Failed to request download link: {}",
-            response.status()
-        ))
-    }
-}
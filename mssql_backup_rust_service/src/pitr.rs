@@ -0,0 +1,301 @@
+use crate::api::BackupEntry;
+use crate::backup::BackupType;
+use anyhow::{anyhow, bail, Result};
+use time::format_description::well_known::Rfc3339;
+use time::macros::format_description;
+use time::{OffsetDateTime, PrimitiveDateTime, UtcOffset};
+
+/// The backups to restore, in order. All but the last are restored WITH
+/// NORECOVERY; the last one with RECOVERY (and STOPAT when `stop_at` is set).
+#[derive(Debug, Clone)]
+pub struct RestorePlan {
+    pub steps: Vec<BackupEntry>,
+    pub stop_at: Option<OffsetDateTime>,
+}
+
+impl RestorePlan {
+    /// Restores a single backup as it was taken.
+    pub fn single(entry: BackupEntry) -> Self {
+        Self {
+            steps: vec![entry],
+            stop_at: None,
+        }
+    }
+}
+
+/// Parses a restore target time: RFC 3339, or `YYYY-MM-DD HH:MM[:SS]` at
+/// `local_offset`, the agent's local offset.
+pub fn parse_stop_at(value: &str, local_offset: Option<UtcOffset>) -> Result<OffsetDateTime> {
+    let value = value.trim();
+    if let Ok(time) = OffsetDateTime::parse(value, &Rfc3339) {
+        return Ok(time);
+    }
+    let local = PrimitiveDateTime::parse(value, format_description!("[year]-[month]-[day] [hour]:[minute]:[second]"))
+        .or_else(|_| PrimitiveDateTime::parse(value, format_description!("[year]-[month]-[day] [hour]:[minute]")))
+        .map_err(|_| anyhow!("Invalid time '{}'; expected YYYY-MM-DD HH:MM[:SS] or RFC 3339", value))?;
+    let offset = local_offset.ok_or_else(|| {
+        anyhow!("The local time offset is unknown; give the time with its offset, e.g. 2026-10-01T12:30:00+02:00")
+    })?;
+    Ok(local.assume_offset(offset))
+}
+
+struct Candidate<'a> {
+    entry: &'a BackupEntry,
+    started: OffsetDateTime,
+    completed: OffsetDateTime,
+}
+
+impl<'a> Candidate<'a> {
+    fn new(entry: &'a BackupEntry) -> Result<Self> {
+        let parse = |value: &str| {
+            OffsetDateTime::parse(value, &Rfc3339)
+                .map_err(|e| anyhow!("Backup {} has an invalid timestamp '{}': {}", entry.id, value, e))
+        };
+        Ok(Self {
+            entry,
+            started: parse(&entry.backup_started_at)?,
+            completed: parse(&entry.backup_completed_at)?,
+        })
+    }
+
+    fn is(&self, backup_type: BackupType) -> bool {
+        self.entry.backup_type == backup_type.as_str()
+    }
+}
+
+/// Picks the backups needed to bring `database` of server `server_id` (any
+/// server when `None`, as long as only one has it) back to `stop_at` from the
+/// API catalog: the latest full backup completed before `stop_at`, the latest
+/// differential based on it, and the unbroken chain of log backups from there
+/// up to the first log backup taken at or after `stop_at`.
+pub fn plan_point_in_time(
+    catalog: &[BackupEntry],
+    server_id: Option<u64>,
+    database: &str,
+    stop_at: OffsetDateTime,
+) -> Result<RestorePlan> {
+    let candidates = catalog
+        .iter()
+        .filter(|entry| entry.db_name == database && entry.status == "success")
+        .filter(|entry| server_id.is_none() || entry.server_id == server_id)
+        .map(Candidate::new)
+        .collect::<Result<Vec<_>>>()?;
+
+    // Databases of the same name on different servers have unrelated LSNs.
+    let mut servers: Vec<Option<u64>> = candidates.iter().map(|c| c.entry.server_id).collect();
+    servers.sort();
+    servers.dedup();
+    if servers.len() > 1 {
+        let servers: Vec<String> = servers.iter().flatten().map(u64::to_string).collect();
+        bail!(
+            "Backups of '{}' come from several servers ({}); choose the source server",
+            database,
+            servers.join(", ")
+        );
+    }
+
+    let full = candidates
+        .iter()
+        .filter(|c| c.is(BackupType::Full) && c.completed <= stop_at && c.entry.last_lsn.is_some())
+        .max_by_key(|c| c.completed)
+        .ok_or_else(|| anyhow!("No full backup of '{}' completed before {}", database, stop_at))?;
+
+    let differential = candidates
+        .iter()
+        .filter(|c| {
+            c.is(BackupType::Differential)
+                && c.completed <= stop_at
+                && c.completed > full.completed
+                && c.entry.last_lsn.is_some()
+                && (c.entry.base_backup_id == Some(full.entry.id)
+                    || (c.entry.database_backup_lsn.is_some()
+                        && c.entry.database_backup_lsn == full.entry.checkpoint_lsn))
+        })
+        .max_by_key(|c| c.completed);
+
+    let mut steps = vec![full.entry.clone()];
+    let base = differential.unwrap_or(full);
+    if let Some(differential) = differential {
+        steps.push(differential.entry.clone());
+    }
+
+    let logs: Vec<&Candidate> = candidates
+        .iter()
+        .filter(|c| c.is(BackupType::Log) && c.entry.first_lsn.is_some() && c.entry.last_lsn.is_some())
+        .collect();
+
+    // Each log backup must contain the LSN the previous step ended at.
+    // Copy-only log backups overlap the regular ones instead of continuing
+    // them exactly, so the one reaching furthest is taken.
+    let mut lsn = base.entry.last_lsn.unwrap_or_default();
+    loop {
+        let next = logs
+            .iter()
+            .filter(|c| c.entry.first_lsn.unwrap_or_default() <= lsn && lsn < c.entry.last_lsn.unwrap_or_default())
+            .max_by_key(|c| c.entry.last_lsn);
+        let Some(next) = next else {
+            let after = steps.last().map(|entry| entry.id).unwrap_or_default();
+            bail!(
+                "The log chain of '{}' does not reach {}: no log backup follows backup {}",
+                database,
+                stop_at,
+                after
+            );
+        };
+        steps.push(next.entry.clone());
+        if next.started >= stop_at {
+            break;
+        }
+        lsn = next.entry.last_lsn.unwrap_or_default();
+    }
+
+    Ok(RestorePlan {
+        steps,
+        stop_at: Some(stop_at),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+    use time::Duration;
+
+    const START: OffsetDateTime = datetime!(2026-10-01 0:00 UTC);
+
+    fn at(minutes: i64) -> OffsetDateTime {
+        START + Duration::minutes(minutes)
+    }
+
+    /// A backup of `Sales` on server 1 taken at minute `minute`, lasting a
+    /// minute, with the LSN range `first..last`.
+    fn entry(id: u64, backup_type: BackupType, minute: i64, first: u128, last: u128) -> BackupEntry {
+        BackupEntry {
+            id,
+            server_id: Some(1),
+            db_name: "Sales".to_string(),
+            backup_type: backup_type.as_str().to_string(),
+            base_backup_id: None,
+            first_lsn: Some(first),
+            last_lsn: Some(last),
+            checkpoint_lsn: Some(first),
+            database_backup_lsn: None,
+            backup_started_at: at(minute).format(&Rfc3339).unwrap(),
+            backup_completed_at: at(minute + 1).format(&Rfc3339).unwrap(),
+            status: "success".to_string(),
+            checksum_sha256: String::new(),
+            compression_codec: None,
+            encryption_algorithm: None,
+            raw_checksum_sha256: None,
+            raw_checksum_hmac_sha256: None,
+        }
+    }
+
+    fn step_ids(plan: &RestorePlan) -> Vec<u64> {
+        plan.steps.iter().map(|entry| entry.id).collect()
+    }
+
+    #[test]
+    fn full_only_does_not_reach_a_later_time() {
+        let catalog = [entry(1, BackupType::Full, 0, 100, 200)];
+        let error = plan_point_in_time(&catalog, None, "Sales", at(30)).unwrap_err();
+        assert!(error.to_string().contains("does not reach"), "{}", error);
+    }
+
+    #[test]
+    fn uses_the_differential_and_the_logs_after_it() {
+        let full = entry(1, BackupType::Full, 0, 100, 200);
+        let mut differential = entry(3, BackupType::Differential, 120, 400, 500);
+        differential.base_backup_id = Some(1);
+        let catalog = [
+            full,
+            entry(2, BackupType::Log, 60, 100, 300),
+            differential,
+            entry(4, BackupType::Log, 180, 300, 600),
+            entry(5, BackupType::Log, 240, 600, 700),
+            entry(6, BackupType::Log, 300, 700, 800),
+        ];
+        let plan = plan_point_in_time(&catalog, None, "Sales", at(200)).unwrap();
+        assert_eq!(step_ids(&plan), [1, 3, 4, 5]);
+        assert_eq!(plan.stop_at, Some(at(200)));
+    }
+
+    #[test]
+    fn stops_inside_the_log_backup_covering_the_time() {
+        let catalog = [
+            entry(1, BackupType::Full, 0, 100, 200),
+            entry(2, BackupType::Log, 60, 100, 300),
+            entry(3, BackupType::Log, 120, 300, 400),
+            entry(4, BackupType::Log, 180, 400, 500),
+        ];
+        let plan = plan_point_in_time(&catalog, None, "Sales", at(90)).unwrap();
+        assert_eq!(step_ids(&plan), [1, 2, 3]);
+    }
+
+    #[test]
+    fn a_gap_in_the_log_chain_is_an_error() {
+        let catalog = [
+            entry(1, BackupType::Full, 0, 100, 200),
+            entry(2, BackupType::Log, 60, 100, 300),
+            entry(4, BackupType::Log, 180, 350, 500),
+        ];
+        let error = plan_point_in_time(&catalog, None, "Sales", at(170)).unwrap_err();
+        assert!(error.to_string().contains("no log backup follows backup 2"), "{}", error);
+    }
+
+    #[test]
+    fn follows_overlapping_copy_only_log_backups() {
+        let catalog = [
+            entry(1, BackupType::Full, 0, 100, 200),
+            entry(2, BackupType::Log, 60, 150, 300),
+            entry(3, BackupType::Log, 120, 250, 450),
+            entry(4, BackupType::Log, 180, 400, 500),
+        ];
+        let plan = plan_point_in_time(&catalog, None, "Sales", at(170)).unwrap();
+        assert_eq!(step_ids(&plan), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn a_time_before_the_first_full_is_an_error() {
+        let catalog = [
+            entry(1, BackupType::Full, 60, 100, 200),
+            entry(2, BackupType::Log, 120, 100, 300),
+        ];
+        let error = plan_point_in_time(&catalog, None, "Sales", at(30)).unwrap_err();
+        assert!(error.to_string().contains("No full backup"), "{}", error);
+    }
+
+    #[test]
+    fn does_not_mix_servers() {
+        let mut other_full = entry(11, BackupType::Full, 30, 5000, 6000);
+        other_full.server_id = Some(2);
+        let catalog = [
+            entry(1, BackupType::Full, 0, 100, 200),
+            entry(2, BackupType::Log, 60, 100, 300),
+            other_full,
+        ];
+        assert!(plan_point_in_time(&catalog, None, "Sales", at(50)).is_err());
+        let plan = plan_point_in_time(&catalog, Some(1), "Sales", at(50)).unwrap();
+        assert_eq!(step_ids(&plan), [1, 2]);
+    }
+
+    #[test]
+    fn parses_local_times_at_the_local_offset() {
+        let offset = Some(UtcOffset::from_hms(2, 0, 0).unwrap());
+        assert_eq!(parse_stop_at("2026-10-01 12:30", offset).unwrap(), datetime!(2026-10-01 10:30 UTC));
+        assert_eq!(parse_stop_at("2026-10-01 12:30:15", offset).unwrap(), datetime!(2026-10-01 10:30:15 UTC));
+        assert_eq!(
+            parse_stop_at("2026-10-01T12:30:00-05:00", offset).unwrap(),
+            datetime!(2026-10-01 17:30 UTC)
+        );
+    }
+
+    #[test]
+    fn local_times_need_a_known_offset() {
+        assert!(parse_stop_at("2026-10-01 12:30", None).is_err());
+        assert_eq!(
+            parse_stop_at("2026-10-01T12:30:00Z", None).unwrap(),
+            datetime!(2026-10-01 12:30 UTC)
+        );
+    }
+}
@@ -0,0 +1,253 @@
+use crate::backup::{BackupType, MssqlClient};
+use crate::sql;
+use anyhow::Result;
+use std::fmt;
+use std::path::Path;
+
+const DATABASE_NOT_ACCESSIBLE: u32 = 976;
+
+/// A check that failed before a BACKUP was issued.
+#[derive(Debug)]
+pub enum PreflightError {
+    DatabaseNotFound {
+        database: String,
+    },
+    DatabaseNotOnline {
+        database: String,
+        state: String,
+    },
+    MissingPermission {
+        database: String,
+        permission: &'static str,
+    },
+    RecoveryModelIncompatible {
+        database: String,
+        recovery_model: String,
+        backup_type: BackupType,
+    },
+    NoDifferentialBase {
+        database: String,
+    },
+    InsufficientSpace {
+        path: String,
+        required_bytes: u64,
+        available_bytes: u64,
+    },
+}
+
+impl fmt::Display for PreflightError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreflightError::DatabaseNotFound { database } => {
+                write!(f, "[database] '{}' does not exist on the server", database)
+            }
+            PreflightError::DatabaseNotOnline { database, state } => {
+                write!(f, "[database] '{}' is {}, not ONLINE", database, state)
+            }
+            PreflightError::MissingPermission { database, permission } => {
+                write!(f, "[permission] the login lacks {} on '{}'", permission, database)
+            }
+            PreflightError::RecoveryModelIncompatible { database, recovery_model, backup_type } => write!(
+                f,
+                "[recovery model] '{}' uses the {} recovery model, which does not allow {} backups",
+                database, recovery_model, backup_type
+            ),
+            PreflightError::NoDifferentialBase { database } => {
+                write!(f, "[recovery model] '{}' has no full backup to base a differential on", database)
+            }
+            PreflightError::InsufficientSpace { path, required_bytes, available_bytes } => write!(
+                f,
+                "[disk space] {} has {} MB free but the backup needs about {} MB",
+                path,
+                available_bytes / (1024 * 1024),
+                required_bytes / (1024 * 1024)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PreflightError {}
+
+/// All checks that failed for one database.
+#[derive(Debug)]
+pub struct PreflightFailed(pub Vec<PreflightError>);
+
+impl fmt::Display for PreflightFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Pre-flight checks failed: ")?;
+        for (i, error) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for PreflightFailed {}
+
+/// Checks that `database` can be backed up as `backup_type` into `temp_path`,
+/// next to a compressed or encrypted copy if `encoded_copy` is set.
+/// Returns a [`PreflightFailed`] listing every check that did not pass.
+pub async fn run_checks(
+    client: &mut MssqlClient,
+    database: &str,
+    backup_type: BackupType,
+    temp_path: &Path,
+    encoded_copy: bool,
+) -> Result<()> {
+    let row = client
+        .query(
+            "SELECT state_desc, recovery_model_desc FROM sys.databases WHERE name = @P1",
+            &[&database],
+        )
+        .await?
+        .into_row()
+        .await?;
+    let Some(row) = row else {
+        return Err(PreflightFailed(vec![PreflightError::DatabaseNotFound {
+            database: database.to_string(),
+        }])
+        .into());
+    };
+    let state = row.get::<&str, _>("state_desc").unwrap_or_default().to_string();
+    let recovery_model = row.get::<&str, _>("recovery_model_desc").unwrap_or_default().to_string();
+    if state != "ONLINE" {
+        return Err(PreflightFailed(vec![PreflightError::DatabaseNotOnline {
+            database: database.to_string(),
+            state,
+        }])
+        .into());
+    }
+
+    let mut failures = Vec::new();
+
+    let permission = match backup_type {
+        BackupType::Log => "BACKUP LOG",
+        _ => "BACKUP DATABASE",
+    };
+    match has_permission(client, database, permission).await {
+        Ok(true) => {}
+        Ok(false) => failures.push(PreflightError::MissingPermission {
+            database: database.to_string(),
+            permission,
+        }),
+        // Availability group secondaries are usually not readable, so their
+        // permissions can't be listed; the BACKUP itself still works.
+        Err(e) if is_database_not_accessible(&e) => tracing::info!(
+            "Database '{}' is not readable on this replica; skipping the {} permission check.",
+            database,
+            permission
+        ),
+        Err(e) => return Err(e),
+    }
+
+    match backup_type {
+        BackupType::Log if recovery_model == "SIMPLE" => {
+            failures.push(PreflightError::RecoveryModelIncompatible {
+                database: database.to_string(),
+                recovery_model,
+                backup_type,
+            });
+        }
+        BackupType::Differential if !has_differential_base(client, database).await? => {
+            failures.push(PreflightError::NoDifferentialBase {
+                database: database.to_string(),
+            });
+        }
+        _ => {}
+    }
+
+    match estimated_backup_bytes(client, database, backup_type).await {
+        Ok(backup_bytes) => {
+            // The encoded copy is written before the backup is deleted, and
+            // can be as large as the backup when it compresses poorly.
+            let required_bytes = if encoded_copy { backup_bytes * 2 } else { backup_bytes };
+            let available_bytes = fs2::available_space(temp_path)?;
+            if required_bytes > available_bytes {
+                let error = PreflightError::InsufficientSpace {
+                    path: temp_path.display().to_string(),
+                    required_bytes,
+                    available_bytes,
+                };
+                // A differential only holds the changed extents, which can't be
+                // estimated up front; the full size is just an upper bound.
+                if backup_type == BackupType::Differential {
+                    tracing::warn!("Database '{}': {}", database, error);
+                } else {
+                    failures.push(error);
+                }
+            }
+        }
+        Err(e) => tracing::warn!("Could not estimate the backup size of '{}': {:?}", database, e),
+    }
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(PreflightFailed(failures).into())
+    }
+}
+
+async fn has_permission(client: &mut MssqlClient, database: &str, permission: &str) -> Result<bool> {
+    let row = client
+        .query(
+            "SELECT COUNT(*) AS granted FROM fn_my_permissions(@P1, 'DATABASE') WHERE permission_name = @P2",
+            &[&database, &permission],
+        )
+        .await?
+        .into_row()
+        .await?;
+    Ok(row.and_then(|row| row.get::<i32, _>("granted")).unwrap_or(0) > 0)
+}
+
+/// Error 976: the database is a non-readable availability group secondary.
+fn is_database_not_accessible(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<tiberius::error::Error>()
+        .and_then(|e| e.code())
+        == Some(DATABASE_NOT_ACCESSIBLE)
+}
+
+async fn has_differential_base(client: &mut MssqlClient, database: &str) -> Result<bool> {
+    let row = client
+        .query(
+            "SELECT CAST(differential_base_lsn AS varchar(30)) AS differential_base_lsn \
+             FROM sys.master_files WHERE database_id = DB_ID(@P1) AND file_id = 1",
+            &[&database],
+        )
+        .await?
+        .into_row()
+        .await?;
+    Ok(row
+        .and_then(|row| row.get::<&str, _>("differential_base_lsn").map(str::to_string))
+        .is_some())
+}
+
+/// Estimates the uncompressed backup size: the used data pages for full and
+/// differential backups, the used log space for log backups.
+async fn estimated_backup_bytes(client: &mut MssqlClient, database: &str, backup_type: BackupType) -> Result<u64> {
+    let row = match backup_type {
+        BackupType::Log => {
+            client
+                .query(
+                    "SELECT CAST(cntr_value AS bigint) * 1024 AS used_bytes FROM sys.dm_os_performance_counters \
+                     WHERE counter_name = 'Log File(s) Used Size (KB)' AND instance_name = @P1",
+                    &[&database],
+                )
+                .await?
+                .into_row()
+                .await?
+        }
+        _ => {
+            let query = format!(
+                "SELECT SUM(CAST(used_pages AS bigint)) * 8192 AS used_bytes FROM {}.sys.allocation_units",
+                sql::quote_ident(database)
+            );
+            client.simple_query(query).await?.into_row().await?
+        }
+    };
+    let used_bytes: i64 = row.and_then(|row| row.get("used_bytes")).unwrap_or(0);
+    Ok(used_bytes.max(0) as u64)
+}
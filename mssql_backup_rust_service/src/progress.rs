@@ -0,0 +1,229 @@
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// A progress update parsed from the informational messages SQL Server sends
+/// while a BACKUP or RESTORE runs with `STATS`.
+#[derive(Debug, Clone)]
+pub enum ProgressEvent {
+    Percent {
+        operation: String,
+        percent: u8,
+        mb_per_sec: Option<f64>,
+    },
+    Completed {
+        operation: String,
+        summary: String,
+    },
+}
+
+struct Operation {
+    name: String,
+    file: PathBuf,
+    /// Size of the file being read; unset while a backup is still writing it.
+    total_bytes: Option<u64>,
+    started: Instant,
+    percent: u8,
+    mb_per_sec: Option<f64>,
+}
+
+/// Running operations by id, oldest first: a log backup can run while a
+/// full backup does.
+static OPERATIONS: Lazy<Mutex<BTreeMap<u64, Operation>>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+tokio::task_local! {
+    /// The operation being polled, which the server messages tiberius logs
+    /// while it runs belong to.
+    static OPERATION_ID: u64;
+}
+
+/// Removes the operation once its future completes or is dropped.
+struct Finish(u64);
+
+impl Drop for Finish {
+    fn drop(&mut self) {
+        OPERATIONS.lock().unwrap().remove(&self.0);
+    }
+}
+
+// Events can't be logged from inside the tracing layer (nested events are
+// dropped), so they are queued here and logged by `log_events`.
+struct EventQueue {
+    tx: UnboundedSender<ProgressEvent>,
+    rx: Mutex<Option<UnboundedReceiver<ProgressEvent>>>,
+}
+
+static EVENTS: Lazy<EventQueue> = Lazy::new(|| {
+    let (tx, rx) = unbounded_channel();
+    EventQueue {
+        tx,
+        rx: Mutex::new(Some(rx)),
+    }
+});
+
+/// Runs `future`, reporting its progress as `name`, which writes or reads
+/// `file`.
+pub async fn track<F: Future>(name: impl Into<String>, file: &Path, total_bytes: Option<u64>, future: F) -> F::Output {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    OPERATIONS.lock().unwrap().insert(
+        id,
+        Operation {
+            name: name.into(),
+            file: file.to_path_buf(),
+            total_bytes,
+            started: Instant::now(),
+            percent: 0,
+            mb_per_sec: None,
+        },
+    );
+    let _finish = Finish(id);
+    OPERATION_ID.scope(id, future).await
+}
+
+/// A one-line description of the running operations for the GUI and tray.
+pub fn status_line() -> Option<String> {
+    let operations = OPERATIONS.lock().unwrap();
+    let lines: Vec<String> = operations
+        .values()
+        .map(|operation| {
+            let mut line = format!("{}: {}%", operation.name, operation.percent);
+            if let Some(mb_per_sec) = operation.mb_per_sec {
+                line.push_str(&format!(" ({:.1} MB/s)", mb_per_sec));
+            }
+            line
+        })
+        .collect();
+    (!lines.is_empty()).then(|| lines.join("; "))
+}
+
+/// Logs progress events as they arrive. Runs for the lifetime of the service.
+pub async fn log_events() {
+    let Some(mut rx) = EVENTS.rx.lock().unwrap().take() else {
+        return;
+    };
+    while let Some(event) = rx.recv().await {
+        match event {
+            ProgressEvent::Percent { operation, percent, mb_per_sec: Some(mb_per_sec) } => {
+                tracing::info!("{}: {}% complete ({:.1} MB/s)", operation, percent, mb_per_sec)
+            }
+            ProgressEvent::Percent { operation, percent, mb_per_sec: None } => {
+                tracing::info!("{}: {}% complete", operation, percent)
+            }
+            ProgressEvent::Completed { operation, summary } => {
+                tracing::info!("{}: {}", operation, summary)
+            }
+        }
+    }
+}
+
+fn on_server_message(message: &str) {
+    let Ok(id) = OPERATION_ID.try_with(|id| *id) else {
+        return;
+    };
+    let mut operations = OPERATIONS.lock().unwrap();
+    let Some(operation) = operations.get_mut(&id) else {
+        return;
+    };
+
+    let event = if let Some(percent) = parse_percent(message) {
+        let elapsed = operation.started.elapsed().as_secs_f64();
+        let bytes_done = match operation.total_bytes {
+            Some(total) => Some(total as f64 * f64::from(percent) / 100.0),
+            None => std::fs::metadata(&operation.file).ok().map(|m| m.len() as f64),
+        };
+        operation.percent = percent;
+        operation.mb_per_sec = bytes_done
+            .filter(|_| elapsed > 0.0)
+            .map(|bytes| bytes / (1024.0 * 1024.0) / elapsed);
+        ProgressEvent::Percent {
+            operation: operation.name.clone(),
+            percent,
+            mb_per_sec: operation.mb_per_sec,
+        }
+    } else if message.contains("successfully processed") {
+        operation.percent = 100;
+        operation.mb_per_sec = parse_mb_per_sec(message).or(operation.mb_per_sec);
+        ProgressEvent::Completed {
+            operation: operation.name.clone(),
+            summary: message.trim().to_string(),
+        }
+    } else {
+        return;
+    };
+
+    let _ = EVENTS.tx.send(event);
+}
+
+/// Parses "40 percent processed."
+fn parse_percent(message: &str) -> Option<u8> {
+    message
+        .trim()
+        .strip_suffix("percent processed.")?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Parses the rate from "... processed 1234 pages in 5.678 seconds (12.345 MB/sec)."
+fn parse_mb_per_sec(message: &str) -> Option<f64> {
+    let end = message.rfind(" MB/sec)")?;
+    let start = message[..end].rfind('(')? + 1;
+    message[start..end].trim().parse().ok()
+}
+
+/// Receives the informational messages tiberius logs for INFO tokens and
+/// turns the `STATS` ones into progress events.
+pub struct ProgressLayer;
+
+impl<S: Subscriber> Layer<S> for ProgressLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if !event.metadata().target().starts_with("tiberius") {
+            return;
+        }
+        let mut visitor = MessageVisitor(None);
+        event.record(&mut visitor);
+        if let Some(message) = visitor.0 {
+            on_server_message(&message);
+        }
+    }
+}
+
+struct MessageVisitor(Option<String>);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.0 = Some(format!("{:?}", value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reports_concurrent_operations_separately() {
+        let file = Path::new("missing.bak");
+        track("Full backup", file, None, async {
+            on_server_message("40 percent processed.");
+            track("Log backup", file, None, async {
+                on_server_message("90 percent processed.");
+                assert_eq!(status_line().as_deref(), Some("Full backup: 40%; Log backup: 90%"));
+            })
+            .await;
+            assert_eq!(status_line().as_deref(), Some("Full backup: 40%"));
+        })
+        .await;
+        assert_eq!(status_line(), None);
+    }
+}
@@ -0,0 +1,489 @@
+use crate::api;
+use crate::backup::{self, server_path_join, BackupLocation, BackupType, MssqlClient};
+use crate::config::Config;
+use crate::encoding;
+use crate::encryption;
+use crate::connection::{Connection, Timeouts};
+use crate::pitr::{self, RestorePlan};
+use crate::progress;
+use crate::sql::{self, WithOptions};
+use anyhow::{anyhow, bail, Result};
+use std::path::Path;
+use tiberius::numeric::Numeric;
+use time::macros::format_description;
+use time::{OffsetDateTime, UtcOffset};
+
+/// What to restore: a single backup, or a database as of a point in time.
+#[derive(Debug, Clone)]
+pub enum RestoreSource {
+    Backup(u64),
+    /// `server_id` is the server that took the backups; `None` is the
+    /// server this agent uploads as.
+    PointInTime {
+        server_id: Option<u64>,
+        database: String,
+        stop_at: OffsetDateTime,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct RestoreRequest {
+    pub source: RestoreSource,
+    pub target_database: String,
+    /// Server-side directory for data files; defaults to the instance default.
+    pub data_dir: Option<String>,
+    /// Server-side directory for log files; defaults to the instance default.
+    pub log_dir: Option<String>,
+    /// Overwrite `target_database` if it exists. Callers must have the user
+    /// confirm this first.
+    pub replace: bool,
+}
+
+/// A file contained in a backup, from `RESTORE FILELISTONLY`.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct BackupFile {
+    pub logical_name: String,
+    pub physical_name: String,
+    /// D (data), L (log), F (full-text catalog) or S (FILESTREAM).
+    pub file_type: String,
+    pub file_group: Option<String>,
+    pub size_bytes: u64,
+}
+
+/// Downloads the backups `request` needs through the API, checks their
+/// checksums and restores them in sequence into `request.target_database`.
+pub async fn restore_backup(config: &Config, request: &RestoreRequest) -> Result<()> {
+    let backups = match &request.source {
+        RestoreSource::PointInTime { server_id: None, .. } => api::fetch_server_backups(config.clone()).await,
+        _ => api::fetch_backups(config.clone()).await,
+    }
+    .map_err(|e| anyhow!(e))?;
+    let plan = match &request.source {
+        RestoreSource::Backup(backup_id) => {
+            let Some(entry) = backups.into_iter().find(|b| b.id == *backup_id) else {
+                bail!("Backup {} not found in the API catalog", backup_id);
+            };
+            RestorePlan::single(entry)
+        }
+        RestoreSource::PointInTime {
+            server_id,
+            database,
+            stop_at,
+        } => pitr::plan_point_in_time(&backups, *server_id, database, *stop_at)?,
+    };
+    for entry in &plan.steps {
+        tracing::info!(
+            "Restore plan for '{}': {} backup {} of '{}' completed at {}",
+            request.target_database,
+            entry.backup_type,
+            entry.id,
+            entry.db_name,
+            entry.backup_completed_at
+        );
+    }
+
+    let mut conn = Connection::new(config);
+    let timeouts = conn.timeouts();
+    let client = conn.client().await?;
+    if timeouts
+        .command("Database lookup", database_exists(client, &request.target_database))
+        .await?
+    {
+        if !request.replace {
+            bail!(
+                "Database '{}' already exists; restoring over it requires REPLACE",
+                request.target_database
+            );
+        }
+        tracing::warn!("Database '{}' exists and will be replaced.", request.target_database);
+    }
+
+    // STOPAT is interpreted in the server's local time.
+    let stop_at = match plan.stop_at {
+        Some(stop_at) => Some(
+            timeouts
+                .command("Server time query", server_local_time(client, stop_at))
+                .await?,
+        ),
+        None => None,
+    };
+
+    let dirs = config.backup.dirs(&config.backup.temp_path);
+    std::fs::create_dir_all(&dirs.agent)?;
+    for (i, entry) in plan.steps.iter().enumerate() {
+        let step = RestoreStep {
+            number: i + 1,
+            count: plan.steps.len(),
+            stop_at: stop_at.as_deref(),
+        };
+        let location = BackupLocation::new(&dirs, &format!("restore_{}.bak", entry.id));
+        let result = download_and_restore(config, &mut conn, request, entry, &step, &location).await;
+        if location.agent.exists() {
+            if let Err(e) = std::fs::remove_file(&location.agent) {
+                tracing::error!("Failed to delete downloaded backup {:?}: {}", location.agent, e);
+            }
+        }
+        if let Err(e) = result {
+            if step.number > 1 {
+                tracing::error!(
+                    "'{}' was left in the RESTORING state after step {} of {}.",
+                    request.target_database,
+                    step.number,
+                    step.count
+                );
+            }
+            return Err(e);
+        }
+    }
+    tracing::info!("Database '{}' restored successfully.", request.target_database);
+
+    Ok(())
+}
+
+struct RestoreStep<'a> {
+    number: usize,
+    count: usize,
+    stop_at: Option<&'a str>,
+}
+
+impl RestoreStep<'_> {
+    fn is_first(&self) -> bool {
+        self.number == 1
+    }
+
+    fn is_last(&self) -> bool {
+        self.number == self.count
+    }
+}
+
+/// Downloads backup `entry` to `dest` and checks its checksum. Compressed
+/// or encrypted backups are decoded, and the result checked against the
+/// checksum of the original backup.
+pub async fn download_backup(config: &Config, entry: &api::BackupEntry, dest: &Path) -> Result<()> {
+    let compressed = match entry.compression_codec.as_deref() {
+        None => false,
+        Some(encoding::ZSTD) => true,
+        Some(codec) => bail!("Backup {} uses the unsupported compression codec '{}'", entry.id, codec),
+    };
+    let encrypted = match entry.encryption_algorithm.as_deref() {
+        None => false,
+        Some(encryption::ALGORITHM) => true,
+        Some(algorithm) => bail!("Backup {} uses the unsupported encryption '{}'", entry.id, algorithm),
+    };
+    let download_path = encoding::encoded_path(dest, compressed, encrypted);
+
+    let url = api::request_download_link(config.clone(), entry.id)
+        .await
+        .map_err(|e| anyhow!(e))?;
+    tracing::info!("Downloading backup {} to {:?}...", entry.id, download_path);
+    let checksum = api::download_file(&url, &download_path).await?;
+    let verified = check_checksum(entry.id, &entry.checksum_sha256, &checksum)?;
+    if download_path == dest {
+        if verified {
+            tracing::info!("Backup {} downloaded and checksum verified.", entry.id);
+        }
+        return Ok(());
+    }
+
+    let action = match (compressed, encrypted) {
+        (true, true) => "Decrypting and decompressing",
+        (false, true) => "Decrypting",
+        _ => "Decompressing",
+    };
+    tracing::info!("{} backup {} to {:?}...", action, entry.id, dest);
+    let result =
+        encoding::decode_file(&download_path, dest, compressed, encrypted, &config.upload.encryption).await;
+    if let Err(e) = std::fs::remove_file(&download_path) {
+        tracing::error!("Failed to delete downloaded backup {:?}: {}", download_path, e);
+    }
+    let raw_checksum = result?;
+    let expected_raw = if encrypted {
+        &entry.raw_checksum_hmac_sha256
+    } else {
+        &entry.raw_checksum_sha256
+    };
+    if check_checksum(entry.id, expected_raw.as_deref().unwrap_or_default(), &raw_checksum)? && verified {
+        tracing::info!("Backup {} downloaded, decoded and checksums verified.", entry.id);
+    }
+    Ok(())
+}
+
+/// Fails on a mismatch; returns false, with a warning, when the catalog has
+/// no checksum to compare with.
+fn check_checksum(backup_id: u64, expected: &str, actual: &str) -> Result<bool> {
+    if expected.is_empty() {
+        tracing::warn!(
+            "Backup {} has no checksum in the catalog; restoring it without verification.",
+            backup_id
+        );
+        return Ok(false);
+    }
+    if !actual.eq_ignore_ascii_case(expected) {
+        bail!(
+            "Checksum mismatch for backup {}: expected {}, downloaded {}",
+            backup_id,
+            expected,
+            actual
+        );
+    }
+    Ok(true)
+}
+
+async fn download_and_restore(
+    config: &Config,
+    conn: &mut Connection,
+    request: &RestoreRequest,
+    entry: &api::BackupEntry,
+    step: &RestoreStep<'_>,
+    location: &BackupLocation,
+) -> Result<()> {
+    download_backup(config, entry, &location.agent).await?;
+
+    let path_str = location.server.as_str();
+    // The download may have taken long enough for the session to drop.
+    let timeouts = conn.timeouts();
+    let client = conn.client().await?;
+    let is_log = entry.backup_type == BackupType::Log.as_str();
+
+    let mut options = WithOptions::new();
+    if step.is_first() {
+        let files = timeouts
+            .command("RESTORE FILELISTONLY", file_list(client, path_str))
+            .await?;
+        let (default_data_dir, default_log_dir) = timeouts
+            .command("Default directory query", default_directories(client))
+            .await?;
+        let data_dir = request.data_dir.clone().unwrap_or(default_data_dir);
+        let log_dir = request.log_dir.clone().unwrap_or(default_log_dir);
+        for file in &files {
+            let dir = if file.file_type == "L" { &log_dir } else { &data_dir };
+            let physical_name = server_path_join(dir, &target_file_name(&request.target_database, file));
+            tracing::info!("Moving '{}' to {}", file.logical_name, physical_name);
+            options.move_file(&file.logical_name, &physical_name);
+        }
+        if request.replace {
+            options.flag("REPLACE");
+        }
+    }
+    if step.is_last() {
+        if let (Some(stop_at), true) = (step.stop_at, is_log) {
+            options.text("STOPAT", stop_at);
+        }
+        options.flag("RECOVERY");
+    } else {
+        options.flag("NORECOVERY");
+    }
+    options.number("STATS", 10);
+
+    let command = if is_log {
+        sql::restore_log(&request.target_database, path_str, &options)
+    } else {
+        sql::restore_database(&request.target_database, path_str, &options)
+    };
+    tracing::info!("Starting restore step {} of {}...", step.number, step.count);
+    let restore = progress::track(
+        format!("Restore of '{}' ({}/{})", request.target_database, step.number, step.count),
+        &location.agent,
+        std::fs::metadata(&location.agent).ok().map(|m| m.len()),
+        client.execute(command, &[]),
+    );
+    let result = timeouts.long_command("RESTORE", restore).await;
+    result.map_err(|e| {
+        tracing::error!("Restore command failed: {:?}", e);
+        e
+    })?;
+
+    Ok(())
+}
+
+/// Formats `time` in the server's current UTC offset, as STOPAT expects.
+async fn server_local_time(client: &mut MssqlClient, time: OffsetDateTime) -> Result<String> {
+    let row = client
+        .simple_query("SELECT DATEPART(TZOFFSET, SYSDATETIMEOFFSET()) AS offset_minutes")
+        .await?
+        .into_row()
+        .await?;
+    let offset_minutes: i32 = row.and_then(|row| row.get("offset_minutes")).unwrap_or(0);
+    let offset = UtcOffset::from_whole_seconds(offset_minutes * 60)?;
+    Ok(time
+        .to_offset(offset)
+        .format(format_description!("[year]-[month]-[day]T[hour]:[minute]:[second]"))?)
+}
+
+/// Prefix of the scratch databases restore tests use; discovery skips them.
+pub const VERIFY_DATABASE_PREFIX: &str = "__orka_verify_";
+
+/// Outcome of restoring a backup into a scratch database and checking it.
+#[derive(Debug, Clone)]
+pub struct RestoreTestResult {
+    pub passed: bool,
+    /// Why the test failed: the restore or `DBCC CHECKDB` error.
+    pub message: Option<String>,
+    pub tested_at: OffsetDateTime,
+}
+
+/// Restores the full backup at `location` into `__orka_verify_<database>`,
+/// runs `DBCC CHECKDB` on it and drops it again.
+pub async fn test_restore(conn: &mut Connection, database: &str, location: &BackupLocation) -> RestoreTestResult {
+    let tested_at = OffsetDateTime::now_utc();
+    let scratch_database = format!("{}{}", VERIFY_DATABASE_PREFIX, database);
+    tracing::info!("Test-restoring '{}' into '{}'...", database, scratch_database);
+    match run_restore_test(conn, &scratch_database, location).await {
+        Ok(()) => {
+            tracing::info!("Restore test of '{}' passed.", database);
+            RestoreTestResult {
+                passed: true,
+                message: None,
+                tested_at,
+            }
+        }
+        Err(e) => {
+            tracing::error!("ALERT: restore test of '{}' failed: {:#}", database, e);
+            RestoreTestResult {
+                passed: false,
+                message: Some(format!("{:#}", e)),
+                tested_at,
+            }
+        }
+    }
+}
+
+async fn run_restore_test(conn: &mut Connection, scratch_database: &str, location: &BackupLocation) -> Result<()> {
+    let timeouts = conn.timeouts();
+
+    // Left over from an interrupted test.
+    timeouts
+        .command("Scratch database cleanup", drop_if_exists(conn.client().await?, scratch_database))
+        .await?;
+
+    let result = restore_and_check(conn.client().await?, &timeouts, scratch_database, location).await;
+    // Fetched again: a timed-out restore or CHECKDB forces a new session.
+    let cleanup = match conn.client().await {
+        Ok(client) => {
+            timeouts
+                .command("Scratch database cleanup", drop_if_exists(client, scratch_database))
+                .await
+        }
+        Err(e) => Err(e),
+    };
+    if let Err(e) = cleanup {
+        tracing::error!("Failed to drop scratch database '{}': {:?}", scratch_database, e);
+    }
+    result
+}
+
+async fn restore_and_check(
+    client: &mut MssqlClient,
+    timeouts: &Timeouts,
+    scratch_database: &str,
+    location: &BackupLocation,
+) -> Result<()> {
+    let files = timeouts
+        .command("RESTORE FILELISTONLY", file_list(client, &location.server))
+        .await?;
+    let (data_dir, log_dir) = timeouts
+        .command("Default directory query", default_directories(client))
+        .await?;
+    let mut options = WithOptions::new();
+    for file in &files {
+        let dir = if file.file_type == "L" { &log_dir } else { &data_dir };
+        options.move_file(
+            &file.logical_name,
+            &server_path_join(dir, &target_file_name(scratch_database, file)),
+        );
+    }
+    options.flag("RECOVERY").number("STATS", 10);
+
+    let restore = progress::track(
+        format!("Test restore into '{}'", scratch_database),
+        &location.agent,
+        std::fs::metadata(&location.agent).ok().map(|m| m.len()),
+        client.execute(sql::restore_database(scratch_database, &location.server, &options), &[]),
+    );
+    let result = timeouts.long_command("Test restore", restore).await;
+    result.map_err(|e| anyhow!("Test restore failed: {}", e))?;
+
+    tracing::info!("Running DBCC CHECKDB on '{}'...", scratch_database);
+    timeouts
+        .long_command("DBCC CHECKDB", client.execute(sql::dbcc_checkdb(scratch_database), &[]))
+        .await
+        .map_err(|e| anyhow!("DBCC CHECKDB failed: {}", e))?;
+
+    Ok(())
+}
+
+async fn drop_if_exists(client: &mut MssqlClient, database: &str) -> Result<()> {
+    if database_exists(client, database).await? {
+        client.simple_query(sql::drop_database(database)).await?.into_results().await?;
+    }
+    Ok(())
+}
+
+/// Whether `database` exists on the configured server.
+pub async fn target_exists(config: &Config, database: &str) -> Result<bool> {
+    let mut conn = Connection::new(config);
+    let timeouts = conn.timeouts();
+    timeouts.command("Database lookup", database_exists(conn.client().await?, database)).await
+}
+
+async fn database_exists(client: &mut MssqlClient, database: &str) -> Result<bool> {
+    let row = client
+        .query("SELECT DB_ID(@P1) AS id", &[&database])
+        .await?
+        .into_row()
+        .await?;
+    Ok(row.and_then(|row| row.get::<i32, _>("id")).is_some())
+}
+
+pub async fn file_list(client: &mut MssqlClient, backup_path: &str) -> Result<Vec<BackupFile>> {
+    let rows = client
+        .simple_query(sql::restore_filelistonly(backup_path))
+        .await?
+        .into_first_result()
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| BackupFile {
+            logical_name: row.get::<&str, _>("LogicalName").unwrap_or_default().to_string(),
+            physical_name: row.get::<&str, _>("PhysicalName").unwrap_or_default().to_string(),
+            file_type: row.get::<&str, _>("Type").unwrap_or_default().to_string(),
+            file_group: row.get::<&str, _>("FileGroupName").map(str::to_string),
+            size_bytes: row
+                .get::<Numeric, _>("Size")
+                .map(|n| n.int_part().max(0) as u64)
+                .unwrap_or(0),
+        })
+        .collect())
+}
+
+async fn default_directories(client: &mut MssqlClient) -> Result<(String, String)> {
+    let row = client
+        .simple_query(
+            "SELECT CAST(SERVERPROPERTY('InstanceDefaultDataPath') AS nvarchar(4000)) AS data_path, \
+                    CAST(SERVERPROPERTY('InstanceDefaultLogPath') AS nvarchar(4000)) AS log_path",
+        )
+        .await?
+        .into_row()
+        .await?
+        .ok_or_else(|| anyhow!("SERVERPROPERTY returned no rows"))?;
+
+    let data_path = row.get::<&str, _>("data_path").map(str::to_string);
+    let log_path = row.get::<&str, _>("log_path").map(str::to_string);
+    match (data_path, log_path) {
+        (Some(data_path), Some(log_path)) => Ok((data_path, log_path)),
+        _ => bail!("The server reports no default data/log directory; pass them explicitly"),
+    }
+}
+
+/// `<target>_<logical name><original extension>`, e.g. `Sales_Copy_Sales_log.ldf`.
+fn target_file_name(target_database: &str, file: &BackupFile) -> String {
+    let original = file.physical_name.rsplit(['\\', '/']).next().unwrap_or_default();
+    let extension = original.rfind('.').map(|i| &original[i..]).unwrap_or("");
+    format!(
+        "{}_{}{}",
+        backup::file_name_safe(target_database),
+        backup::file_name_safe(&file.logical_name),
+        extension
+    )
+}
@@ -0,0 +1,52 @@
+use crate::backup::BackupType;
+use crate::config::ScheduleConfig;
+use time::{Duration, OffsetDateTime, Weekday};
+
+const DEFAULT_FULL_INTERVAL_DAYS: u32 = 7;
+
+/// Decides whether this cycle takes a full or a differential backup of a
+/// database whose last full backup started at `last_full`. `now` is local
+/// time, which `full_weekday` refers to.
+pub fn choose_backup_type(
+    schedule: &ScheduleConfig,
+    last_full: Option<OffsetDateTime>,
+    now: OffsetDateTime,
+) -> BackupType {
+    if !schedule.differential {
+        return BackupType::Full;
+    }
+    let Some(last_full) = last_full else {
+        tracing::info!("No full backup recorded yet; taking a full backup.");
+        return BackupType::Full;
+    };
+
+    let interval_days = schedule.full_interval_days.unwrap_or(DEFAULT_FULL_INTERVAL_DAYS);
+    if now - last_full >= Duration::days(interval_days.into()) {
+        return BackupType::Full;
+    }
+
+    if let Some(weekday) = schedule.full_weekday.as_deref() {
+        match parse_weekday(weekday) {
+            Some(weekday) if now.weekday() == weekday && last_full.to_offset(now.offset()).date() != now.date() => {
+                return BackupType::Full;
+            }
+            Some(_) => {}
+            None => tracing::warn!("Unknown full_weekday '{}'; ignoring it.", weekday),
+        }
+    }
+
+    BackupType::Differential
+}
+
+fn parse_weekday(s: &str) -> Option<Weekday> {
+    match s.to_ascii_lowercase().as_str() {
+        "monday" | "mon" => Some(Weekday::Monday),
+        "tuesday" | "tue" => Some(Weekday::Tuesday),
+        "wednesday" | "wed" => Some(Weekday::Wednesday),
+        "thursday" | "thu" => Some(Weekday::Thursday),
+        "friday" | "fri" => Some(Weekday::Friday),
+        "saturday" | "sat" => Some(Weekday::Saturday),
+        "sunday" | "sun" => Some(Weekday::Sunday),
+        _ => None,
+    }
+}
@@ -0,0 +1,182 @@
+//! Builders for the BACKUP/RESTORE/DBCC statements the agent sends. Names
+//! and paths are always quoted; option keywords are `&'static str` so only
+//! constants from this code base end up in the statement unquoted.
+
+/// Quotes `name` as a delimited identifier, like T-SQL `QUOTENAME(name)`.
+pub fn quote_ident(name: &str) -> String {
+    format!("[{}]", name.replace(']', "]]"))
+}
+
+/// Quotes `value` as a Unicode string literal.
+pub fn quote_literal(value: &str) -> String {
+    format!("N'{}'", value.replace('\'', "''"))
+}
+
+/// The `WITH` clause of a BACKUP or RESTORE statement.
+#[derive(Debug, Default, Clone)]
+pub struct WithOptions(Vec<String>);
+
+impl WithOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a keyword option such as `COPY_ONLY`.
+    pub fn flag(&mut self, option: &'static str) -> &mut Self {
+        self.0.push(option.to_string());
+        self
+    }
+
+    /// Adds a numeric option such as `STATS = 10`.
+    pub fn number(&mut self, option: &'static str, value: u64) -> &mut Self {
+        self.0.push(format!("{} = {}", option, value));
+        self
+    }
+
+    /// Adds a string option such as `NAME = N'...'`.
+    pub fn text(&mut self, option: &'static str, value: &str) -> &mut Self {
+        self.0.push(format!("{} = {}", option, quote_literal(value)));
+        self
+    }
+
+    /// Adds `MOVE N'logical' TO N'physical'` for a RESTORE.
+    pub fn move_file(&mut self, logical_name: &str, physical_name: &str) -> &mut Self {
+        self.0.push(format!(
+            "MOVE {} TO {}",
+            quote_literal(logical_name),
+            quote_literal(physical_name)
+        ));
+        self
+    }
+
+    fn render(&self) -> String {
+        if self.0.is_empty() {
+            String::new()
+        } else {
+            format!(" WITH {}", self.0.join(", "))
+        }
+    }
+}
+
+pub fn backup_database(database: &str, path: &str, options: &WithOptions) -> String {
+    format!(
+        "BACKUP DATABASE {} TO DISK = {}{}",
+        quote_ident(database),
+        quote_literal(path),
+        options.render()
+    )
+}
+
+pub fn backup_log(database: &str, path: &str, options: &WithOptions) -> String {
+    format!(
+        "BACKUP LOG {} TO DISK = {}{}",
+        quote_ident(database),
+        quote_literal(path),
+        options.render()
+    )
+}
+
+pub fn restore_verifyonly(path: &str, options: &WithOptions) -> String {
+    format!(
+        "RESTORE VERIFYONLY FROM DISK = {}{}",
+        quote_literal(path),
+        options.render()
+    )
+}
+
+pub fn restore_headeronly(path: &str) -> String {
+    format!("RESTORE HEADERONLY FROM DISK = {}", quote_literal(path))
+}
+
+pub fn restore_filelistonly(path: &str) -> String {
+    format!("RESTORE FILELISTONLY FROM DISK = {}", quote_literal(path))
+}
+
+pub fn restore_database(database: &str, path: &str, options: &WithOptions) -> String {
+    format!(
+        "RESTORE DATABASE {} FROM DISK = {}{}",
+        quote_ident(database),
+        quote_literal(path),
+        options.render()
+    )
+}
+
+pub fn restore_log(database: &str, path: &str, options: &WithOptions) -> String {
+    format!(
+        "RESTORE LOG {} FROM DISK = {}{}",
+        quote_ident(database),
+        quote_literal(path),
+        options.render()
+    )
+}
+
+pub fn dbcc_checkdb(database: &str) -> String {
+    format!("DBCC CHECKDB ({}) WITH NO_INFOMSGS", quote_ident(database))
+}
+
+/// Drops `database`, disconnecting any other sessions first.
+pub fn drop_database(database: &str) -> String {
+    let database = quote_ident(database);
+    format!(
+        "ALTER DATABASE {} SET SINGLE_USER WITH ROLLBACK IMMEDIATE; DROP DATABASE {}",
+        database, database
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backup::file_name_safe;
+
+    const HOSTILE: &str = "x]; DROP DATABASE master; --'";
+
+    #[test]
+    fn quotes_identifiers() {
+        assert_eq!(quote_ident("a]b"), "[a]]b]");
+        assert_eq!(quote_ident("]]"), "[]]]]]");
+        assert_eq!(quote_ident("plain"), "[plain]");
+    }
+
+    #[test]
+    fn quotes_literals() {
+        assert_eq!(quote_literal("O'Brien"), "N'O''Brien'");
+        assert_eq!(quote_literal("''"), "N''''''");
+    }
+
+    #[test]
+    fn backup_database_quotes_hostile_names() {
+        let mut options = WithOptions::new();
+        options.flag("COPY_ONLY").text("NAME", HOSTILE);
+        assert_eq!(
+            backup_database(HOSTILE, "C:\\b\\it's.bak", &options),
+            "BACKUP DATABASE [x]]; DROP DATABASE master; --'] TO DISK = N'C:\\b\\it''s.bak' \
+             WITH COPY_ONLY, NAME = N'x]; DROP DATABASE master; --'''"
+        );
+    }
+
+    #[test]
+    fn restore_database_quotes_hostile_names() {
+        let mut options = WithOptions::new();
+        options.move_file("data'; --", "D:\\x];.mdf").flag("NORECOVERY");
+        assert_eq!(
+            restore_database(HOSTILE, "/b/a';b.bak", &options),
+            "RESTORE DATABASE [x]]; DROP DATABASE master; --'] FROM DISK = N'/b/a'';b.bak' \
+             WITH MOVE N'data''; --' TO N'D:\\x];.mdf', NORECOVERY"
+        );
+    }
+
+    #[test]
+    fn dbcc_checkdb_quotes_hostile_names() {
+        assert_eq!(
+            dbcc_checkdb(HOSTILE),
+            "DBCC CHECKDB ([x]]; DROP DATABASE master; --']) WITH NO_INFOMSGS"
+        );
+    }
+
+    #[test]
+    fn file_names_stay_in_their_directory() {
+        assert_eq!(file_name_safe("../x"), "___x");
+        assert_eq!(file_name_safe("a\\b/c"), "a_b_c");
+        assert_eq!(file_name_safe(HOSTILE), "x___DROP_DATABASE_master__--_");
+    }
+}
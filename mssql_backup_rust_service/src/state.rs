@@ -0,0 +1,165 @@
+use crate::backup::{BackupLsns, BackupType};
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use time::OffsetDateTime;
+use tokio::sync::Mutex;
+
+const STATE_PATH: &str = "agent_state.json";
+
+// Serializes read-modify-write cycles on the state file between tasks.
+static STATE_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+/// Local record of what the agent has backed up, kept next to `config.toml`.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct AgentState {
+    #[serde(default)]
+    pub databases: HashMap<String, DatabaseState>,
+    /// The newest `msdb` backup set already checked for foreign backups.
+    #[serde(default)]
+    pub history_backup_set_id: Option<i32>,
+    /// Chunked uploads that were started but not finished.
+    #[serde(default)]
+    pub pending_uploads: Vec<PendingUpload>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct DatabaseState {
+    /// The last full backup taken; differentials are based on it.
+    pub last_full: Option<BackupRecord>,
+    /// The last log backup taken; the next one must continue its chain.
+    #[serde(default)]
+    pub last_log: Option<BackupRecord>,
+    /// Set when the log chain broke in a way only a new full backup can repair.
+    #[serde(default)]
+    pub full_required: bool,
+    /// Set when a full backup taken elsewhere replaced the differential base:
+    /// the next scheduled backup is a full, but the log chain is intact.
+    #[serde(default)]
+    pub differential_base_lost: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackupRecord {
+    pub file_name: String,
+    /// Id assigned by the API on upload, if it returned one.
+    pub backup_id: Option<u64>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub started_at: OffsetDateTime,
+    #[serde(default)]
+    pub lsns: Option<BackupLsns>,
+    /// Taken COPY_ONLY, so it is not the server's differential base.
+    #[serde(default)]
+    pub copy_only: bool,
+}
+
+/// An upload session the API has acknowledged part of. It is resumed from
+/// the API's offset, also after the agent restarts.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PendingUpload {
+    pub upload_id: String,
+    pub path: PathBuf,
+    pub file_size_bytes: u64,
+    pub chunk_size_bytes: u64,
+    /// Metadata sent when the upload is completed.
+    pub fields: Vec<(String, String)>,
+    pub database: String,
+    pub backup_type: BackupType,
+    /// Its `backup_id` is filled in once the upload completes.
+    pub record: BackupRecord,
+}
+
+/// Returns the stored state of `database`, or an empty state if none exists.
+pub async fn database_state(database: &str) -> Result<DatabaseState> {
+    let _guard = STATE_LOCK.lock().await;
+    Ok(load_state()?.databases.get(database).cloned().unwrap_or_default())
+}
+
+/// Applies `f` to the stored state of `database` and writes it back.
+pub async fn update_database<F>(database: &str, f: F) -> Result<()>
+where
+    F: FnOnce(&mut DatabaseState),
+{
+    let _guard = STATE_LOCK.lock().await;
+    let mut state = load_state()?;
+    f(state.databases.entry(database.to_string()).or_default());
+    save_state(&state)
+}
+
+/// Sets the API id of backup `file_name` if it is still the last full or log
+/// backup of `database`; newer backups are left alone.
+pub async fn set_backup_id(database: &str, file_name: &str, backup_id: Option<u64>) -> Result<()> {
+    update_database(database, |db| {
+        for record in [db.last_full.as_mut(), db.last_log.as_mut()].into_iter().flatten() {
+            if record.file_name == file_name {
+                record.backup_id = backup_id;
+            }
+        }
+    })
+    .await
+}
+
+pub async fn history_watermark() -> Result<Option<i32>> {
+    let _guard = STATE_LOCK.lock().await;
+    Ok(load_state()?.history_backup_set_id)
+}
+
+pub async fn set_history_watermark(backup_set_id: i32) -> Result<()> {
+    let _guard = STATE_LOCK.lock().await;
+    let mut state = load_state()?;
+    state.history_backup_set_id = Some(backup_set_id);
+    save_state(&state)
+}
+
+pub async fn pending_uploads() -> Result<Vec<PendingUpload>> {
+    let _guard = STATE_LOCK.lock().await;
+    Ok(load_state()?.pending_uploads)
+}
+
+/// Stores `upload`, replacing any earlier session for the same file.
+pub async fn save_pending_upload(upload: PendingUpload) -> Result<()> {
+    let _guard = STATE_LOCK.lock().await;
+    let mut state = load_state()?;
+    state.pending_uploads.retain(|pending| pending.path != upload.path);
+    state.pending_uploads.push(upload);
+    save_state(&state)
+}
+
+pub async fn remove_pending_upload(upload_id: &str) -> Result<()> {
+    let _guard = STATE_LOCK.lock().await;
+    let mut state = load_state()?;
+    state.pending_uploads.retain(|pending| pending.upload_id != upload_id);
+    save_state(&state)
+}
+
+/// Writes a temporary file and renames it over the state file, so a crash
+/// never leaves a partly written state behind.
+fn save_state(state: &AgentState) -> Result<()> {
+    let content = serde_json::to_string_pretty(state)?;
+    let temp_path = format!("{}.tmp", STATE_PATH);
+    let mut file = fs::File::create(&temp_path)?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&temp_path, STATE_PATH)?;
+    Ok(())
+}
+
+/// A state file that can't be read is an error rather than an empty state:
+/// saving over it would lose the log chains and interrupted uploads in it.
+fn load_state() -> Result<AgentState> {
+    if !Path::new(STATE_PATH).exists() {
+        return Ok(AgentState::default());
+    }
+    let content =
+        fs::read_to_string(STATE_PATH).with_context(|| format!("Failed to read the agent state from {}", STATE_PATH))?;
+    serde_json::from_str(&content).with_context(|| {
+        format!(
+            "The agent state in {} is damaged; repair or move it away to start over with full backups",
+            STATE_PATH
+        )
+    })
+}
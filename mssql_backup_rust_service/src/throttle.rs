@@ -0,0 +1,163 @@
+use crate::config::{self, ThrottleConfig};
+use crate::local_time;
+use bytes::Bytes;
+use futures::Stream;
+use once_cell::sync::Lazy;
+use std::io;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+use time::macros::format_description;
+use time::Time;
+
+const CONFIG_PATH: &str = "config.toml";
+/// How often config.toml is checked for changed settings.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+/// Size of the pieces a request body is sent in, so a limited upload flows
+/// evenly instead of in bursts.
+const PIECE_SIZE: usize = 64 * 1024;
+
+/// One token bucket for every upload, so concurrent uploads share the limit.
+static BUCKET: Lazy<Mutex<Bucket>> = Lazy::new(|| {
+    Mutex::new(Bucket {
+        tokens: 0.0,
+        refilled: Instant::now(),
+    })
+});
+static SETTINGS: Lazy<Mutex<Settings>> = Lazy::new(|| Mutex::new(Settings::default()));
+
+struct Bucket {
+    /// Negative while the uploads are ahead of the limit.
+    tokens: f64,
+    refilled: Instant,
+}
+
+struct Window {
+    start: Time,
+    end: Time,
+    limit: Option<f64>,
+}
+
+impl Window {
+    fn contains(&self, time: Time) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+#[derive(Default)]
+struct Settings {
+    limit: Option<f64>,
+    windows: Vec<Window>,
+    checked: Option<Instant>,
+    modified: Option<SystemTime>,
+    /// The limit last in effect, to log when it changes.
+    current: Option<Option<f64>>,
+}
+
+impl Settings {
+    /// Re-reads the throttle settings when config.toml has changed.
+    fn reload(&mut self) {
+        let first = self.checked.replace(Instant::now()).is_none();
+        let modified = std::fs::metadata(CONFIG_PATH).and_then(|m| m.modified()).ok();
+        if !first && modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+        match config::load_config(CONFIG_PATH) {
+            Ok(config) => self.apply(&config.upload.throttle),
+            Err(e) => tracing::warn!("Keeping the upload limits; failed to reload {}: {:#}", CONFIG_PATH, e),
+        }
+    }
+
+    fn apply(&mut self, throttle: &ThrottleConfig) {
+        self.limit = bytes_per_sec(throttle.limit_mb_per_sec);
+        self.windows = throttle
+            .windows
+            .iter()
+            .filter_map(|window| match (parse_time(&window.start), parse_time(&window.end)) {
+                (Some(start), Some(end)) => Some(Window {
+                    start,
+                    end,
+                    limit: bytes_per_sec(window.limit_mb_per_sec),
+                }),
+                _ => {
+                    tracing::warn!(
+                        "Ignoring the upload window {}-{}: times must be HH:MM.",
+                        window.start,
+                        window.end
+                    );
+                    None
+                }
+            })
+            .collect();
+    }
+
+    /// The limit in bytes per second at `time`; `None` is unlimited.
+    fn limit_at(&mut self, time: Time) -> Option<f64> {
+        let limit = self
+            .windows
+            .iter()
+            .find(|window| window.contains(time))
+            .map_or(self.limit, |window| window.limit);
+        if self.current != Some(limit) {
+            match limit {
+                Some(limit) => tracing::info!("Upload bandwidth limited to {:.2} MB/s.", limit / 1024.0 / 1024.0),
+                None => tracing::info!("Upload bandwidth unlimited."),
+            }
+            self.current = Some(limit);
+        }
+        limit
+    }
+}
+
+fn bytes_per_sec(mb_per_sec: Option<f64>) -> Option<f64> {
+    mb_per_sec.filter(|mb| *mb > 0.0).map(|mb| mb * 1024.0 * 1024.0)
+}
+
+fn parse_time(value: &str) -> Option<Time> {
+    Time::parse(value.trim(), format_description!("[hour]:[minute]")).ok()
+}
+
+/// The limit in effect now, from the current settings in config.toml.
+fn current_limit() -> Option<f64> {
+    let mut settings = SETTINGS.lock().unwrap();
+    if !matches!(settings.checked, Some(checked) if checked.elapsed() < RELOAD_INTERVAL) {
+        settings.reload();
+    }
+    settings.limit_at(local_time::now().time())
+}
+
+/// Waits until `bytes` may be sent under the current limit.
+pub async fn acquire(bytes: usize) {
+    let Some(rate) = current_limit() else {
+        return;
+    };
+    let wait = {
+        let mut bucket = BUCKET.lock().unwrap();
+        let now = Instant::now();
+        // Allow bursts of at most one second's worth.
+        let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(rate);
+        bucket.refilled = now;
+        bucket.tokens -= bytes as f64;
+        Duration::from_secs_f64((-bucket.tokens / rate).max(0.0))
+    };
+    if !wait.is_zero() {
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// `data` as a request body stream that keeps to the limit.
+pub fn stream(data: Bytes) -> impl Stream<Item = io::Result<Bytes>> {
+    futures::stream::unfold(data, |mut rest| async move {
+        if rest.is_empty() {
+            return None;
+        }
+        let piece = rest.split_to(rest.len().min(PIECE_SIZE));
+        acquire(piece.len()).await;
+        Some((Ok(piece), rest))
+    })
+}
@@ -1,97 +1,592 @@
-use crate::config::Config;
-use anyhow::{Result, anyhow};
-use reqwest::multipart;
+use crate::backup::{BackupHeader, BackupLsns, BackupType};
+use crate::config::{Config, UploadConfig};
+use crate::encoding;
+use crate::encryption;
+use crate::restore::RestoreTestResult;
+use crate::state::{self, BackupRecord, PendingUpload};
+use crate::throttle;
+use anyhow::{Result, anyhow, bail};
+use bytes::Bytes;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
 use tokio::fs::File;
-use tokio_util::codec::{BytesCodec, FramedRead};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use sha2::{Sha256, Digest};
+use std::collections::HashSet;
+use std::io::SeekFrom;
 use std::path::Path;
+use std::sync::Mutex;
 use std::time::Duration;
 use time::{OffsetDateTime, format_description::well_known::Rfc3339};
 
+/// Consecutive failed requests after which an upload is given up; it is
+/// resumed on the next backup cycle.
+const MAX_FAILURES: u32 = 10;
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Files with an upload running in this process, so a resumed upload never
+/// runs next to the one that started it.
+static ACTIVE_UPLOADS: Lazy<Mutex<HashSet<String>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
 pub struct BackupMeta {
+    pub database_name: String,
+    pub backup_type: BackupType,
+    pub copy_only: bool,
+    /// API id of the full backup a differential is based on.
+    pub base_backup_id: Option<u64>,
+    pub lsns: Option<BackupLsns>,
+    pub header: Option<BackupHeader>,
+    /// Set when the backup was test-restored before the upload.
+    pub restore_test: Option<RestoreTestResult>,
     pub start_time: OffsetDateTime,
     pub end_time: OffsetDateTime,
     pub duration_seconds: i64,
     pub filepath: std::path::PathBuf,
 }
 
-pub async fn upload_backup(config: &Config, meta: BackupMeta) -> Result<()> {
-    let checksum = calculate_checksum(&meta.filepath).await?;
+impl BackupMeta {
+    /// The state record of this backup, without the API id.
+    pub fn record(&self) -> BackupRecord {
+        BackupRecord {
+            file_name: self
+                .filepath
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            backup_id: None,
+            started_at: self.start_time,
+            lsns: self.lsns.clone(),
+            copy_only: self.copy_only,
+        }
+    }
+
+    /// The metadata fields sent when the upload is completed. The checksum
+    /// is added then, once the whole file has been read.
+    fn fields(&self) -> Result<Vec<(String, String)>> {
+        let mut fields = vec![
+            ("backup_type", self.backup_type.as_str().to_string()),
+            ("is_copy_only", if self.copy_only { "1" } else { "0" }.to_string()),
+            ("backup_started_at", self.start_time.format(&Rfc3339)?),
+            ("backup_completed_at", self.end_time.format(&Rfc3339)?),
+            ("duration_seconds", self.duration_seconds.to_string()),
+        ];
+        if let Some(base_backup_id) = self.base_backup_id {
+            fields.push(("base_backup_id", base_backup_id.to_string()));
+        }
+        if let Some(lsns) = &self.lsns {
+            fields.extend([
+                ("first_lsn", lsns.first_lsn.clone()),
+                ("last_lsn", lsns.last_lsn.clone()),
+                ("checkpoint_lsn", lsns.checkpoint_lsn.clone()),
+                ("database_backup_lsn", lsns.database_backup_lsn.clone()),
+            ]);
+        }
+        if let Some(header) = &self.header {
+            fields.extend([
+                ("sql_server_version", header.software_version.clone()),
+                ("database_version", header.database_version.to_string()),
+                ("compatibility_level", header.compatibility_level.to_string()),
+                ("collation", header.collation.clone()),
+                ("backup_size_bytes", header.backup_size_bytes.to_string()),
+                ("file_list", serde_json::to_string(&header.files)?),
+            ]);
+            if let Some(compressed_size_bytes) = header.compressed_size_bytes {
+                fields.push(("compressed_size_bytes", compressed_size_bytes.to_string()));
+            }
+        }
+        if let Some(restore_test) = &self.restore_test {
+            fields.extend([
+                ("restore_test_status", if restore_test.passed { "passed" } else { "failed" }.to_string()),
+                ("restore_tested_at", restore_test.tested_at.format(&Rfc3339)?),
+            ]);
+            if let Some(message) = &restore_test.message {
+                fields.push(("restore_test_message", message.clone()));
+            }
+        }
+        Ok(fields.into_iter().map(|(name, value)| (name.to_string(), value)).collect())
+    }
+}
+
+/// The API's view of an upload session.
+#[derive(Deserialize, Debug)]
+struct SessionStatus {
+    upload_id: String,
+    received_bytes: u64,
+    chunk_size_bytes: u64,
+}
+
+/// Uploads the backup and returns the id the API assigned to it, if any.
+/// The file is sent in chunks; an interrupted upload continues from the last
+/// chunk the API acknowledged, including one left over from before a restart.
+/// With compression or encryption enabled the backup is encoded first and
+/// the encoded file is uploaded; `checksum_sha256` is always that of the
+/// uploaded bytes and `raw_checksum_sha256` that of the backup itself, or
+/// `raw_checksum_hmac_sha256` when it is encrypted.
+pub async fn upload_backup(config: &Config, meta: &BackupMeta) -> Result<Option<u64>> {
+    let settings = &config.upload;
+    let upload_path = encoding::encoded_path(&meta.filepath, settings.compression.enabled, settings.encryption.enabled);
+    let existing = state::pending_uploads().await?.into_iter().find(|pending| {
+        pending.path == upload_path
+            && std::fs::metadata(&pending.path).is_ok_and(|file| file.len() == pending.file_size_bytes)
+    });
+    let upload = match existing {
+        Some(upload) => upload,
+        None => {
+            let mut fields = meta.fields()?;
+            if upload_path != meta.filepath {
+                fields.extend(encode(&meta.filepath, &upload_path, settings).await?);
+            }
+            let file_size = tokio::fs::metadata(&upload_path).await?.len();
+            let session = with_retries("Starting the upload", || start_session(config, meta, &upload_path, file_size)).await?;
+            let upload = PendingUpload {
+                upload_id: session.upload_id,
+                path: upload_path,
+                file_size_bytes: file_size,
+                chunk_size_bytes: session.chunk_size_bytes,
+                fields,
+                database: meta.database_name.clone(),
+                backup_type: meta.backup_type,
+                record: meta.record(),
+            };
+            state::save_pending_upload(upload.clone()).await?;
+            if upload.path != meta.filepath {
+                remove_file(&meta.filepath).await;
+            }
+            upload
+        }
+    };
+    resume_upload(config, &upload).await
+}
+
+/// Compresses and/or encrypts the backup to `target` and returns the
+/// metadata fields describing how.
+async fn encode(source: &Path, target: &Path, settings: &UploadConfig) -> Result<Vec<(String, String)>> {
+    let raw_size = tokio::fs::metadata(source).await?.len();
+    let key_id = if settings.encryption.enabled {
+        Some(settings.encryption.current_key()?.id.clone())
+    } else {
+        None
+    };
+    match &key_id {
+        Some(key_id) if settings.compression.enabled => tracing::info!(
+            "Compressing {:?} with zstd level {} and encrypting it with key '{}'...",
+            source,
+            settings.compression.level(),
+            key_id
+        ),
+        Some(key_id) => tracing::info!("Encrypting {:?} with key '{}'...", source, key_id),
+        None => tracing::info!("Compressing {:?} with zstd level {}...", source, settings.compression.level()),
+    }
+    let raw_checksum = encoding::encode_file(source, target, settings).await?;
+
+    let raw_checksum_field = if key_id.is_some() {
+        "raw_checksum_hmac_sha256"
+    } else {
+        "raw_checksum_sha256"
+    };
+    let mut fields = vec![("raw_size_bytes", raw_size.to_string()), (raw_checksum_field, raw_checksum)];
+    if settings.compression.enabled {
+        let compressed_size = tokio::fs::metadata(target).await?.len();
+        tracing::info!(
+            "Compressed {} bytes to {} bytes ({:.1}%).",
+            raw_size,
+            compressed_size,
+            compressed_size as f64 * 100.0 / raw_size.max(1) as f64
+        );
+        fields.push(("compression_codec", encoding::ZSTD.to_string()));
+    }
+    if let Some(key_id) = key_id {
+        fields.extend([
+            ("encryption_algorithm", encryption::ALGORITHM.to_string()),
+            ("encryption_key_id", key_id),
+        ]);
+    }
+    Ok(fields.into_iter().map(|(name, value)| (name.to_string(), value)).collect())
+}
+
+async fn remove_file(path: &Path) {
+    if let Err(e) = tokio::fs::remove_file(path).await {
+        tracing::error!("Failed to delete local backup file {:?}: {}", path, e);
+    } else {
+        tracing::info!("Local backup file {:?} deleted.", path);
+    }
+}
+
+/// Sends the rest of `upload` and completes it, returning the API's backup
+/// id. The local file is deleted once the API has it.
+pub async fn resume_upload(config: &Config, upload: &PendingUpload) -> Result<Option<u64>> {
+    let key = upload.path.to_string_lossy().into_owned();
+    if !ACTIVE_UPLOADS.lock().unwrap().insert(key.clone()) {
+        bail!("{:?} is already being uploaded", upload.path);
+    }
+    let result = send_upload(config, upload).await;
+    ACTIVE_UPLOADS.lock().unwrap().remove(&key);
+
+    let backup_id = result?;
+    state::remove_pending_upload(&upload.upload_id).await?;
+    remove_file(&upload.path).await;
+    Ok(backup_id)
+}
+
+/// SHA-256 of the whole file, fed with each chunk once the API has
+/// acknowledged it, so the file is read only once. After a resume, the part
+/// the API already has is read again to catch up.
+struct FileHash {
+    hasher: Sha256,
+    hashed_bytes: u64,
+}
+
+impl FileHash {
+    fn new() -> Self {
+        Self {
+            hasher: Sha256::new(),
+            hashed_bytes: 0,
+        }
+    }
+
+    /// Hashes the file up to `offset`, reading only what isn't hashed yet.
+    async fn catch_up(&mut self, file: &mut File, offset: u64) -> Result<()> {
+        if offset < self.hashed_bytes {
+            *self = Self::new();
+        }
+        if offset == self.hashed_bytes {
+            return Ok(());
+        }
+        tracing::info!("Reading the first {} bytes to continue the checksum...", offset);
+        file.seek(SeekFrom::Start(self.hashed_bytes)).await?;
+        let mut buffer = vec![0; 1024 * 1024];
+        while self.hashed_bytes < offset {
+            let length = buffer.len().min((offset - self.hashed_bytes) as usize);
+            file.read_exact(&mut buffer[..length]).await?;
+            self.update(&buffer[..length]);
+        }
+        Ok(())
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+        self.hashed_bytes += data.len() as u64;
+    }
+
+    fn hex(&self) -> String {
+        format!("{:x}", self.hasher.clone().finalize())
+    }
+}
+
+enum Progress {
+    Chunk(u64),
+    Completed(Option<u64>),
+}
 
+async fn send_upload(config: &Config, upload: &PendingUpload) -> Result<Option<u64>> {
     let client = reqwest::Client::new();
-    let mut attempts = 0;
-    let max_attempts = 10;
+    let mut file = File::open(&upload.path).await?;
+    let mut offset = None;
+    let mut failures = 0;
     let mut delay = Duration::from_secs(1);
+    let mut logged_percent = 0;
+    let mut hash = FileHash::new();
 
+    tracing::info!("Uploading {:?} ({} bytes) as upload {}...", upload.path, upload.file_size_bytes, upload.upload_id);
     loop {
-        attempts += 1;
-        tracing::info!("Uploading backup... Attempt {}/{}", attempts, max_attempts);
-
-        let file = File::open(&meta.filepath).await?;
-        let stream = FramedRead::new(file, BytesCodec::new());
-        let file_body = reqwest::Body::wrap_stream(stream);
-
-        let file_part = multipart::Part::stream(file_body)
-            .file_name(meta.filepath.file_name().unwrap().to_str().unwrap().to_string())
-            .mime_str("application/octet-stream")?;
-
-        let form = multipart::Form::new()
-            .text("token", config.api.server_token.clone())
-            .text("database_name", config.mssql.database.clone())
-            .text("backup_started_at", meta.start_time.format(&Rfc3339)?)
-            .text("backup_completed_at", meta.end_time.format(&Rfc3339)?)
-            .text("duration_seconds", meta.duration_seconds.to_string())
-            .text("checksum_sha256", checksum.clone())
-            .part("backup_file", file_part);
-
-        let upload_url = format!("{}/api/backups/upload", config.api.url);
-        let response_result = client.post(&upload_url)
-            .bearer_auth(&config.api.auth_token)
-            .header("Accept", "application/json")
-            .multipart(form)
-            .send()
-            .await;
-
-        match response_result {
-            Ok(response) => {
-                if response.status().is_success() {
-                    let json: serde_json::Value = response.json().await?;
-                    if json.get("status").and_then(|s| s.as_str()) == Some("ok") {
-                        tracing::info!("Upload successful.");
-                        return Ok(());
-                    } else {
-                        tracing::error!("API error: {:?}", json);
-                    }
-                } else {
-                    tracing::error!("Upload failed with status: {}", response.status());
+        match advance(&client, config, upload, &mut file, &mut hash, offset).await {
+            Ok(Progress::Chunk(received_bytes)) => {
+                offset = Some(received_bytes);
+                failures = 0;
+                delay = Duration::from_secs(1);
+                let percent = received_bytes * 100 / upload.file_size_bytes.max(1);
+                if percent >= logged_percent + 10 {
+                    logged_percent = percent - percent % 10;
+                    tracing::info!("Uploaded {}% of {:?}", logged_percent, upload.path);
                 }
-            },
+            }
+            Ok(Progress::Completed(backup_id)) => {
+                tracing::info!("Upload successful.");
+                return Ok(backup_id);
+            }
             Err(e) => {
-                tracing::error!("Upload request failed: {}", e);
+                failures += 1;
+                if failures >= MAX_FAILURES {
+                    return Err(e.context(format!("Upload failed after {} consecutive attempts", MAX_FAILURES)));
+                }
+                tracing::error!("Upload request failed ({}/{}): {:#}", failures, MAX_FAILURES, e);
+                // Ask the API where to continue, in case the failed request
+                // was stored after all.
+                offset = None;
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_RETRY_DELAY);
             }
         }
+    }
+}
 
-        if attempts >= max_attempts {
-            return Err(anyhow!("Upload failed after {} attempts.", max_attempts));
-        }
+/// Sends the next chunk, or completes the upload once the API has all of it.
+async fn advance(
+    client: &reqwest::Client,
+    config: &Config,
+    upload: &PendingUpload,
+    file: &mut File,
+    hash: &mut FileHash,
+    offset: Option<u64>,
+) -> Result<Progress> {
+    let received_bytes = match offset {
+        Some(offset) => offset,
+        None => session_status(client, config, &upload.upload_id).await?.received_bytes,
+    };
+    hash.catch_up(file, received_bytes.min(upload.file_size_bytes)).await?;
+    if received_bytes >= upload.file_size_bytes {
+        return complete_session(client, config, upload, &hash.hex()).await.map(Progress::Completed);
+    }
+
+    let length = upload.chunk_size_bytes.min(upload.file_size_bytes - received_bytes);
+    let mut chunk = vec![0; length as usize];
+    file.seek(SeekFrom::Start(received_bytes)).await?;
+    file.read_exact(&mut chunk).await?;
+    let chunk = Bytes::from(chunk);
+    let checksum = format!("{:x}", Sha256::digest(&chunk));
+
+    let index = received_bytes / upload.chunk_size_bytes;
+    let response = client
+        .put(session_url(config, &upload.upload_id, &format!("/chunks/{}", index)))
+        .bearer_auth(&config.api.auth_token)
+        .header("Accept", "application/json")
+        .header("Upload-Offset", received_bytes.to_string())
+        .header("Upload-Checksum-Sha256", checksum)
+        .header(reqwest::header::CONTENT_LENGTH, length)
+        .body(reqwest::Body::wrap_stream(throttle::stream(chunk.clone())))
+        .send()
+        .await?;
+    let status: SessionStatus = parse_response(response, "Chunk upload").await?;
+    hash.update(&chunk);
+    Ok(Progress::Chunk(status.received_bytes))
+}
+
+async fn start_session(config: &Config, meta: &BackupMeta, path: &Path, file_size: u64) -> Result<SessionStatus> {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| anyhow!("Backup path {:?} has no file name", path))?;
+    let response = reqwest::Client::new()
+        .post(format!("{}/api/backups/uploads", config.api.url))
+        .bearer_auth(&config.api.auth_token)
+        .header("Accept", "application/json")
+        .json(&serde_json::json!({
+            "token": config.api.server_token,
+            "database_name": meta.database_name,
+            "file_name": file_name,
+            "file_size_bytes": file_size,
+            "chunk_size_bytes": config.api.chunk_size_bytes(),
+        }))
+        .send()
+        .await?;
+    parse_response(response, "Starting the upload").await
+}
+
+async fn session_status(client: &reqwest::Client, config: &Config, upload_id: &str) -> Result<SessionStatus> {
+    let response = client
+        .get(session_url(config, upload_id, ""))
+        .bearer_auth(&config.api.auth_token)
+        .header("Accept", "application/json")
+        .send()
+        .await?;
+    parse_response(response, "Upload status").await
+}
+
+/// Completes the upload with the checksum of the whole file, which the API
+/// checks before it creates the backup. Completing an already completed
+/// upload returns the same backup id.
+async fn complete_session(
+    client: &reqwest::Client,
+    config: &Config,
+    upload: &PendingUpload,
+    checksum: &str,
+) -> Result<Option<u64>> {
+    let mut fields: Vec<(&str, &str)> = upload
+        .fields
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .filter(|(name, _)| *name != "checksum_sha256")
+        .collect();
+    fields.push(("checksum_sha256", checksum));
+    let response = client
+        .post(session_url(config, &upload.upload_id, "/complete"))
+        .bearer_auth(&config.api.auth_token)
+        .header("Accept", "application/json")
+        .form(&fields)
+        .send()
+        .await?;
+    let json: serde_json::Value = parse_response(response, "Completing the upload").await?;
+    Ok(json.get("backup_id").and_then(|id| id.as_u64()))
+}
+
+fn session_url(config: &Config, upload_id: &str, path: &str) -> String {
+    format!("{}/api/backups/uploads/{}{}", config.api.url, upload_id, path)
+}
+
+async fn parse_response<T: serde::de::DeserializeOwned>(response: reqwest::Response, what: &str) -> Result<T> {
+    let status = response.status();
+    let json: serde_json::Value = response.json().await.unwrap_or_default();
+    if !status.is_success() || json.get("status").and_then(|s| s.as_str()) != Some("ok") {
+        bail!("{} failed with status {}: {}", what, status, json);
+    }
+    Ok(serde_json::from_value(json)?)
+}
 
+async fn with_retries<T, F, Fut>(what: &str, mut request: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T>>,
+{
+    let mut attempts = 0;
+    let mut delay = Duration::from_secs(1);
+    loop {
+        attempts += 1;
+        match request().await {
+            Ok(value) => return Ok(value),
+            Err(e) if attempts >= MAX_FAILURES => return Err(e),
+            Err(e) => tracing::error!("{} failed ({}/{}): {:#}", what, attempts, MAX_FAILURES, e),
+        }
         tokio::time::sleep(delay).await;
-        delay *= 2; // Exponential backoff
+        delay = (delay * 2).min(MAX_RETRY_DELAY);
     }
 }
 
-async fn calculate_checksum(path: &Path) -> Result<String> {
-    let mut file = File::open(path).await?;
-    let mut hasher = Sha256::new();
-    let mut buffer = [0; 1024 * 64]; // 64KB buffer
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{TcpListener, TcpStream};
 
-    loop {
-        let n = tokio::io::AsyncReadExt::read(&mut file, &mut buffer).await?;
-        if n == 0 {
-            break;
+    const CHUNK_SIZE: u64 = 1024;
+
+    /// What the stand-in API has received.
+    #[derive(Default)]
+    struct Received {
+        data: Vec<u8>,
+        /// (chunk index, Upload-Offset) of every chunk request.
+        chunks: Vec<(u64, u64)>,
+        completions: Vec<String>,
+        dropped: bool,
+    }
+
+    /// A stand-in for the upload API that drops the connection halfway
+    /// through the first attempt at chunk 1.
+    async fn serve(listener: TcpListener, received: Arc<Mutex<Received>>) {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(serve_connection(stream, received.clone()));
+        }
+    }
+
+    async fn serve_connection(stream: TcpStream, received: Arc<Mutex<Received>>) {
+        let mut stream = BufReader::new(stream);
+        loop {
+            let mut request_line = String::new();
+            if stream.read_line(&mut request_line).await.unwrap_or(0) == 0 {
+                return;
+            }
+            let mut headers = Vec::new();
+            loop {
+                let mut line = String::new();
+                stream.read_line(&mut line).await.unwrap();
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                let (name, value) = line.split_once(':').unwrap();
+                headers.push((name.to_ascii_lowercase(), value.trim().to_string()));
+            }
+            let header = |name: &str| headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.clone());
+            let length: usize = header("content-length").map_or(0, |v| v.parse().unwrap());
+            let mut parts = request_line.split_whitespace();
+            let (method, path) = (parts.next().unwrap().to_string(), parts.next().unwrap().to_string());
+
+            let json = if let Some(index) = path.split("/chunks/").nth(1) {
+                let index: u64 = index.parse().unwrap();
+                let offset: u64 = header("upload-offset").unwrap().parse().unwrap();
+                received.lock().unwrap().chunks.push((index, offset));
+                let drop_now = index == 1 && !std::mem::replace(&mut received.lock().unwrap().dropped, true);
+                if drop_now {
+                    let mut partial = vec![0; length / 2];
+                    stream.read_exact(&mut partial).await.unwrap();
+                    return;
+                }
+                let mut body = vec![0; length];
+                stream.read_exact(&mut body).await.unwrap();
+                let mut received = received.lock().unwrap();
+                assert_eq!(offset, received.data.len() as u64, "chunk sent at the wrong offset");
+                received.data.extend_from_slice(&body);
+                status_json(received.data.len())
+            } else if path.ends_with("/complete") {
+                let mut body = vec![0; length];
+                stream.read_exact(&mut body).await.unwrap();
+                received.lock().unwrap().completions.push(String::from_utf8(body).unwrap());
+                serde_json::json!({ "status": "ok", "backup_id": 42 })
+            } else {
+                assert_eq!(method, "GET");
+                status_json(received.lock().unwrap().data.len())
+            };
+            let body = json.to_string();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.get_mut().write_all(response.as_bytes()).await.unwrap();
         }
-        hasher.update(&buffer[..n]);
     }
 
-    Ok(format!("{:x}", hasher.finalize()))
+    fn status_json(received_bytes: usize) -> serde_json::Value {
+        serde_json::json!({
+            "status": "ok",
+            "upload_id": "test-upload",
+            "received_bytes": received_bytes,
+            "chunk_size_bytes": CHUNK_SIZE,
+        })
+    }
+
+    #[tokio::test]
+    async fn resumes_from_the_acknowledged_offset_after_a_dropped_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut config = Config::default();
+        config.api.url = format!("http://{}", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(Received::default()));
+        tokio::spawn(serve(listener, received.clone()));
+
+        let data: Vec<u8> = (0..2500u32).map(|i| (i % 251) as u8).collect();
+        let path = std::env::temp_dir().join(format!("upload_test_{}.bak", std::process::id()));
+        std::fs::write(&path, &data).unwrap();
+        let upload = PendingUpload {
+            upload_id: "test-upload".to_string(),
+            path: path.clone(),
+            file_size_bytes: data.len() as u64,
+            chunk_size_bytes: CHUNK_SIZE,
+            fields: vec![("backup_type".to_string(), "full".to_string())],
+            database: "Sales".to_string(),
+            backup_type: BackupType::Full,
+            record: BackupRecord {
+                file_name: "upload_test.bak".to_string(),
+                backup_id: None,
+                started_at: OffsetDateTime::now_utc(),
+                lsns: None,
+                copy_only: false,
+            },
+        };
+
+        let backup_id = send_upload(&config, &upload).await;
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(backup_id.unwrap(), Some(42));
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.data, data);
+        assert_eq!(received.chunks, [(0, 0), (1, 1024), (1, 1024), (2, 2048)]);
+        assert_eq!(received.completions.len(), 1);
+        let checksum = format!("{:x}", Sha256::digest(&data));
+        assert!(
+            received.completions[0].contains(&format!("checksum_sha256={}", checksum)),
+            "{}",
+            received.completions[0]
+        );
+    }
 }